@@ -1,16 +1,16 @@
-use crate::download;
+use crate::chapters::{self, Chapters};
 use crate::structs::*;
+use crate::transcript;
 use crate::utils;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clap_complete::{generate, Shell};
-use download::download_episodes;
 use futures::prelude::*;
 use regex::Regex;
+use reqwest::{header, StatusCode};
 
-use rss::Channel;
 use std::collections::HashSet;
 use std::fs::{self, File};
-use std::io::{self, BufReader, BufWriter, Write};
+use std::io::{self, BufWriter, Write};
 use std::path::PathBuf;
 
 pub fn list_episodes(search: &str) -> Result<()> {
@@ -20,9 +20,7 @@ pub fn list_episodes(search: &str) -> Result<()> {
     for entry in fs::read_dir(&path)? {
         let entry = entry?;
         if re.is_match(&entry.file_name().into_string().unwrap()) {
-            let file = File::open(&entry.path())?;
-            let channel = Channel::read_from(BufReader::new(file))?;
-            let podcast = Podcast::from(channel);
+            let podcast = Podcast::parse(&fs::read(entry.path())?)?;
             let episodes = podcast.episodes();
             let stdout = io::stdout();
             let mut handle = stdout.lock();
@@ -31,13 +29,25 @@ pub fn list_episodes(search: &str) -> Result<()> {
                 .filter(|ep| ep.title().is_some())
                 .enumerate()
                 .for_each(|(num, ep)| {
-                    writeln!(
-                        &mut handle,
-                        "({}) {}",
-                        episodes.len() - num,
-                        ep.title().unwrap()
-                    )
-                    .ok();
+                    let details = episode_details(ep);
+                    if details.is_empty() {
+                        writeln!(
+                            &mut handle,
+                            "({}) {}",
+                            episodes.len() - num,
+                            ep.title().unwrap()
+                        )
+                        .ok();
+                    } else {
+                        writeln!(
+                            &mut handle,
+                            "({}) {} [{}]",
+                            episodes.len() - num,
+                            ep.title().unwrap(),
+                            details.join(", ")
+                        )
+                        .ok();
+                    }
                 });
             return Ok(());
         }
@@ -45,15 +55,39 @@ pub fn list_episodes(search: &str) -> Result<()> {
     Ok(())
 }
 
+/// Season and episode number, running time and content flags, as far as the feed gives them
+fn episode_details(episode: &Episode) -> Vec<String> {
+    let mut details = vec![];
+    match (episode.season(), episode.number()) {
+        (Some(season), Some(number)) => details.push(format!("S{}E{}", season, number)),
+        (Some(season), None) => details.push(format!("S{}", season)),
+        (None, Some(number)) => details.push(format!("E{}", number)),
+        (None, None) => {}
+    }
+    if let Some(duration) = episode.duration() {
+        details.push(utils::format_duration(duration));
+    }
+    match episode.episode_type() {
+        Some(EpisodeType::Full) | None => {}
+        Some(episode_type) => details.push(episode_type.to_string()),
+    }
+    if episode.explicit() == Some(true) {
+        details.push("explicit".into());
+    }
+    details
+}
+
+/// Fetches a subscription's feed, returning the updated subscription
+/// and the new episodes to download
 pub async fn update_subscription(
     state: &State,
     index: usize,
     sub: &Subscription,
     config: &Config,
-) -> Result<[usize; 2]> {
+    full: bool,
+) -> Result<(usize, Subscription, Vec<Download>)> {
     println!("Updating {}", sub.title);
-    let mut path: PathBuf = utils::get_podcast_dir()?;
-    path.push(&sub.title);
+    let path: PathBuf = sub.download_dir()?;
     utils::create_dir_if_not_exist(&path)?;
 
     let mut titles = HashSet::new();
@@ -64,25 +98,72 @@ pub async fn update_subscription(
         ));
     }
 
-    let resp = reqwest::get(&sub.url).await?.bytes().await?;
-    let podcast = Podcast::from(Channel::read_from(BufReader::new(&resp[..]))?);
+    let cached_rss_path = sub.xml_path()?;
+
+    // Only ask for a conditional response if we still have the feed it would refer to
+    let mut headers = sub.auth_headers()?;
+    if cached_rss_path.exists() && !full {
+        if let Some(etag) = sub.etag.as_ref().and_then(|v| v.parse().ok()) {
+            headers.insert(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = sub.last_modified.as_ref().and_then(|v| v.parse().ok()) {
+            headers.insert(header::IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+
+    let feed = utils::fetch_feed(&sub.url, headers, config.retries()).await?;
+    let mut sub = sub.clone();
+    if let Some(url) = &feed.moved_to {
+        sub.move_to(url);
+    }
+    let (podcast, latest) = if feed.response.status() == StatusCode::NOT_MODIFIED {
+        // Nothing new, but an episode whose download failed last time is still unseen
+        let podcast = Podcast::parse(&fs::read(&cached_rss_path)?)?;
+        let latest = podcast.episodes();
+        (podcast, latest)
+    } else {
+        let resp = feed.response.error_for_status()?;
+        sub.set_cache_headers(resp.headers());
+        let page_url = feed.url;
 
-    let mut podcast_rss_path = utils::get_xml_dir()?;
-    let title = utils::append_extension(podcast.title(), "xml");
-    podcast_rss_path.push(title);
+        let resp = resp.bytes().await?;
+        let (mut podcast, repairs) = Podcast::parse_lenient(&resp)?;
+        report_repairs(sub.title(), &repairs);
+        // Only the first page can have new episodes. The older pages are the back catalogue,
+        // which shouldn't be downloaded just because a full refresh fetched it.
+        let latest = podcast.episodes();
+        if full {
+            podcast
+                .fetch_older_pages(&page_url, &sub.auth_headers()?, config.retries())
+                .await;
+        } else if podcast.next_page(&page_url).is_some() && cached_rss_path.exists() {
+            // Keep the older pages fetched before, which aren't in the first page
+            podcast.merge(&Podcast::parse(&fs::read(&cached_rss_path)?)?);
+        }
+        if let Some(url) = podcast.new_feed_url() {
+            sub.move_to(url);
+        }
+        if let Some(guid) = podcast.guid() {
+            sub.guid = Some(guid);
+        }
 
-    let file = File::create(&podcast_rss_path)?;
-    (*podcast).write_to(BufWriter::new(file))?;
+        // Cached under our name for the podcast, which stays put if the publisher renames it
+        let file = File::create(&cached_rss_path)?;
+        podcast.write_to(BufWriter::new(file))?;
+        (podcast, latest)
+    };
 
-    if sub.num_episodes < podcast.episodes().len() {
-        let episodes = podcast.episodes()[..podcast.episodes().len() - sub.num_episodes].to_vec();
-        let to_download = match config.download_subscription_limit {
+    let all_episodes = podcast.episodes();
+    let episodes = sub.new_episodes(&latest);
+    let mut to_download = vec![];
+    if !episodes.is_empty() {
+        to_download = match sub.download_subscription_limit(config) {
             Some(subscription_limit) => {
                 let download_futures = episodes
                     .iter()
                     .rev()
                     .take(subscription_limit as usize)
-                    .map(|ep| Download::new(state, &podcast, ep));
+                    .map(|ep| Download::new(state, &sub, &podcast, ep));
 
                 stream::iter(download_futures)
                     .filter_map(|download| async move { download.await.ok() })
@@ -91,7 +172,9 @@ pub async fn update_subscription(
                     .await
             }
             None => {
-                let download_futures = episodes.iter().map(|ep| Download::new(state, &podcast, ep));
+                let download_futures = episodes
+                    .iter()
+                    .map(|ep| Download::new(state, &sub, &podcast, ep));
 
                 stream::iter(download_futures)
                     .filter_map(|download| async move { download.await.ok() })
@@ -100,16 +183,281 @@ pub async fn update_subscription(
                     .await
             }
         };
-        download_episodes(to_download).await?;
     }
-    Ok([index, podcast.episodes().len()])
+
+    sub.mark_seen(&all_episodes);
+    // What's about to be downloaded is only seen once it's downloaded, so that a failed
+    // download is tried again on the next refresh
+    for download in &to_download {
+        sub.set_seen(&download.id, false);
+    }
+    Ok((index, sub, to_download))
+}
+
+/// Looks up an episode of the first matching subscription, reporting when there's none
+fn find_episode<'a>(
+    state: &'a State,
+    p_search: &str,
+    e_search: &str,
+) -> Result<Option<(&'a Subscription, Podcast, Episode)>> {
+    let re_pod = Regex::new(&format!("(?i){}", &p_search))?;
+    let sub = match state
+        .subscriptions
+        .iter()
+        .find(|sub| re_pod.is_match(sub.title()))
+    {
+        Some(sub) => sub,
+        None => {
+            eprintln!("No subscription matches {}", p_search);
+            return Ok(None);
+        }
+    };
+    let podcast = Podcast::from_title(&sub.title)?;
+    match podcast.find_episode(e_search) {
+        Some(episode) => Ok(Some((sub, podcast, episode))),
+        None => {
+            eprintln!("No episode of {} matches {}", sub.title(), e_search);
+            Ok(None)
+        }
+    }
+}
+
+/// Fetches something the subscription's feed links to, as long as it's allowed to link to it
+async fn fetch_linked(state: &State, sub: &Subscription, url: &str) -> Result<Vec<u8>> {
+    if !utils::may_link_to(&sub.url, url) {
+        return Err(anyhow!("{} links to a local file: {}", sub.title(), url));
+    }
+    utils::fetch(&state.client, url, sub.auth_headers()?).await
+}
+
+/// Prints the chapters of an episode, from the copy saved with it or else from the feed
+pub async fn print_chapters(state: &State, p_search: &str, e_search: &str) -> Result<()> {
+    let (sub, podcast, episode) = match find_episode(state, p_search, e_search)? {
+        Some(found) => found,
+        None => return Ok(()),
+    };
+
+    let saved = sub
+        .episode_path(&state.config, &podcast, &episode)?
+        .map(|path| chapters::path(&path))
+        .filter(|path| path.exists());
+    let content = match (saved, episode.chapters_url()) {
+        (Some(path), _) => fs::read(path)?,
+        (None, Some(url)) => fetch_linked(state, sub, url).await?,
+        (None, None) => {
+            eprintln!("{} has no chapters", episode.title().unwrap_or_default());
+            return Ok(());
+        }
+    };
+
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    for line in Chapters::parse(&content)?.lines() {
+        writeln!(&mut handle, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// Prints the transcript of an episode, from the copy saved with it or else from the feed
+pub async fn print_transcript(state: &State, p_search: &str, e_search: &str) -> Result<()> {
+    let (sub, podcast, episode) = match find_episode(state, p_search, e_search)? {
+        Some(found) => found,
+        None => return Ok(()),
+    };
+
+    let saved = sub
+        .episode_path(&state.config, &podcast, &episode)?
+        .and_then(|path| transcript::find(&path));
+    let cues = match (saved, episode.transcript()) {
+        (Some((path, _)), _) => transcript::read(&path)?,
+        (None, Some(link)) => {
+            let content = fetch_linked(state, sub, &link.url).await?;
+            transcript::parse(link.format, &String::from_utf8_lossy(&content))?
+        }
+        (None, None) => {
+            eprintln!("{} has no transcript", episode.title().unwrap_or_default());
+            return Ok(());
+        }
+    };
+
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    for cue in cues {
+        writeln!(&mut handle, "{}", cue.line())?;
+    }
+    Ok(())
+}
+
+/// Searches the transcripts saved with downloaded episodes
+pub fn grep_transcripts(state: &State, search: &str) -> Result<()> {
+    let re = Regex::new(&format!("(?i){}", &search))?;
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    for sub in &state.subscriptions {
+        let dir = sub.download_dir()?;
+        if !dir.exists() {
+            continue;
+        }
+        let mut paths: Vec<PathBuf> = fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect();
+        paths.sort();
+        for path in paths {
+            let episode = match transcript::episode_name(&path) {
+                Some(episode) => episode,
+                None => continue,
+            };
+            let cues = match transcript::read(&path) {
+                Ok(cues) => cues,
+                Err(err) => {
+                    eprintln!("Couldn't read {}: {}", path.display(), err);
+                    continue;
+                }
+            };
+            for cue in cues.iter().filter(|cue| re.is_match(&cue.text)) {
+                writeln!(&mut handle, "{} / {}: {}", sub.title(), episode, cue.line())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints what the cached feed says about a podcast
+pub fn print_info(state: &State, p_search: &str) -> Result<()> {
+    let re_pod = Regex::new(&format!("(?i){}", &p_search))?;
+    let sub = match state
+        .subscriptions
+        .iter()
+        .find(|sub| re_pod.is_match(sub.title()))
+    {
+        Some(sub) => sub,
+        None => {
+            eprintln!("No subscription matches {}", p_search);
+            return Ok(());
+        }
+    };
+    let podcast = Podcast::from_title(&sub.title)?;
+    let episodes = podcast.episodes();
+    let dates: Vec<_> = episodes.iter().filter_map(Episode::pub_date).collect();
+
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    writeln!(&mut handle, "{}", sub.title())?;
+    if podcast.title() != sub.title() {
+        writeln!(&mut handle, "  Published as: {}", podcast.title())?;
+    }
+    writeln!(&mut handle, "  Subscribed URL: {}", sub.url)?;
+    if !podcast.url().is_empty() {
+        writeln!(&mut handle, "  Link: {}", podcast.url())?;
+    }
+    if let Some(author) = podcast.author() {
+        writeln!(&mut handle, "  Author: {}", author)?;
+    }
+    if let Some(language) = podcast.language() {
+        writeln!(&mut handle, "  Language: {}", language)?;
+    }
+    let categories = podcast.categories();
+    if !categories.is_empty() {
+        writeln!(&mut handle, "  Categories: {}", categories.join(", "))?;
+    }
+    if let Some(image) = podcast.image() {
+        writeln!(&mut handle, "  Artwork: {}", image)?;
+    }
+    if let Some(explicit) = podcast.explicit() {
+        writeln!(
+            &mut handle,
+            "  Explicit: {}",
+            if explicit { "yes" } else { "no" }
+        )?;
+    }
+    writeln!(&mut handle, "  Episodes: {}", episodes.len())?;
+    if let (Some(first), Some(last)) = (dates.iter().min(), dates.iter().max()) {
+        writeln!(
+            &mut handle,
+            "  First published: {}",
+            first.format("%Y-%m-%d")
+        )?;
+        writeln!(&mut handle, "  Last published: {}", last.format("%Y-%m-%d"))?;
+    }
+    if let Some(guid) = podcast.guid() {
+        writeln!(&mut handle, "  GUID: {}", guid)?;
+    }
+    match podcast.locked() {
+        Some((locked, Some(owner))) => writeln!(
+            &mut handle,
+            "  Locked: {} (owner: {})",
+            if locked { "yes" } else { "no" },
+            owner
+        )?,
+        Some((locked, None)) => writeln!(
+            &mut handle,
+            "  Locked: {}",
+            if locked { "yes" } else { "no" }
+        )?,
+        None => {}
+    }
+    for funding in podcast.funding() {
+        match funding.text {
+            Some(text) => writeln!(&mut handle, "  Funding: {} ({})", text, funding.url)?,
+            None => writeln!(&mut handle, "  Funding: {}", funding.url)?,
+        }
+    }
+    for person in podcast.persons() {
+        let mut line = person.name;
+        if let Some(role) = person.role {
+            line = format!("{}, {}", line, role);
+        }
+        if let Some(href) = person.href {
+            line = format!("{} ({})", line, href);
+        }
+        writeln!(&mut handle, "  Person: {}", line)?;
+    }
+    if let Some(description) = podcast.description() {
+        writeln!(&mut handle, "\n{}", description)?;
+    }
+    Ok(())
+}
+
+pub fn list_subscriptions(state: &State, tag: Option<&str>, show_archived: bool) -> Result<()> {
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    for subscription in state
+        .subscriptions
+        .iter()
+        .filter(|sub| show_archived || sub.status != SubscriptionStatus::Archived)
+        .filter(|sub| tag.map(|tag| sub.tags.contains(tag)).unwrap_or(true))
+    {
+        match subscription.status {
+            SubscriptionStatus::Active => writeln!(&mut handle, "{}", subscription.title())?,
+            SubscriptionStatus::Paused => {
+                writeln!(&mut handle, "{} (paused)", subscription.title())?
+            }
+            SubscriptionStatus::Archived => {
+                writeln!(&mut handle, "{} (archived)", subscription.title())?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Patterns matching exactly the subscriptions with the given tag, optionally narrowed down by a regex
+pub fn tagged_podcasts(state: &State, tag: &str, p_search: Option<&str>) -> Result<Vec<String>> {
+    let re_pod = Regex::new(&format!("(?i){}", p_search.unwrap_or("")))?;
+    Ok(state
+        .subscriptions
+        .iter()
+        .filter(|sub| sub.tags.contains(tag) && re_pod.is_match(sub.title()))
+        .map(|sub| format!("^{}$", regex::escape(sub.title())))
+        .collect())
 }
 
-pub fn list_subscriptions(state: &State) -> Result<()> {
+pub fn list_subscription_config(sub: &Subscription) -> Result<()> {
     let stdout = io::stdout();
     let mut handle = stdout.lock();
-    for subscription in &state.subscriptions {
-        writeln!(&mut handle, "{}", subscription.title())?;
+    writeln!(&mut handle, "{}", sub.title())?;
+    for key in SubscriptionConfig::KEYS {
+        let value = sub.config.get(key)?.unwrap_or_else(|| "(global)".into());
+        writeln!(&mut handle, "  {}: {}", key, value)?;
     }
     Ok(())
 }
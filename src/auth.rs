@@ -0,0 +1,216 @@
+use anyhow::{anyhow, Result};
+use reqwest::header::{self, HeaderMap, HeaderName, HeaderValue};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+lazy_static! {
+    // Secrets are only looked up once per run, so password managers only prompt once
+    static ref RESOLVED: Mutex<HashMap<Secret, String>> = Mutex::new(HashMap::new());
+}
+
+/// Where to find a secret. Only this reference is saved, never the secret itself.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Secret {
+    /// The value of an environment variable
+    Env(String),
+    /// The first line printed by a shell command, e.g. `pass show podcast/foo`
+    Command(String),
+}
+
+impl Secret {
+    pub fn resolve(&self) -> Result<String> {
+        let mut resolved = RESOLVED.lock().unwrap();
+        if let Some(secret) = resolved.get(self) {
+            return Ok(secret.clone());
+        }
+
+        let secret = match self {
+            Secret::Env(name) => std::env::var(name)
+                .map_err(|_| anyhow!("Environment variable {} isn't set", name))?,
+            Secret::Command(command) => {
+                // Let the command talk to the user, in case it needs to ask for a passphrase
+                let output = shell(command)
+                    .stdin(Stdio::inherit())
+                    .stderr(Stdio::inherit())
+                    .output()?;
+                if !output.status.success() {
+                    return Err(anyhow!("`{}` failed with {}", command, output.status));
+                }
+                String::from_utf8(output.stdout)?
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .to_string()
+            }
+        };
+        resolved.insert(self.clone(), secret.clone());
+        Ok(secret)
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.args(["-c", command]);
+    shell
+}
+
+#[cfg(target_os = "windows")]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.args(["/C", command]);
+    shell
+}
+
+impl FromStr for Secret {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            Some(("env", name)) if !name.is_empty() => Ok(Secret::Env(name.to_string())),
+            Some(("cmd", command)) if !command.is_empty() => {
+                Ok(Secret::Command(command.to_string()))
+            }
+            _ => Err(anyhow!(
+                "'{}' should look like env:VARIABLE or cmd:COMMAND",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Secret::Env(name) => write!(f, "env:{}", name),
+            Secret::Command(command) => write!(f, "cmd:{}", command),
+        }
+    }
+}
+
+/// Credentials sent with every request for a subscription's feed and episodes
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Auth {
+    Basic { username: String, password: Secret },
+    Bearer { token: Secret },
+    Header { name: String, value: Secret },
+}
+
+impl Auth {
+    fn header(&self) -> Result<(HeaderName, HeaderValue)> {
+        let (name, value) = match self {
+            Auth::Basic { username, password } => (
+                header::AUTHORIZATION,
+                format!(
+                    "Basic {}",
+                    base64::encode(format!("{}:{}", username, password.resolve()?))
+                ),
+            ),
+            Auth::Bearer { token } => (
+                header::AUTHORIZATION,
+                format!("Bearer {}", token.resolve()?),
+            ),
+            Auth::Header { name, value } => (name.parse()?, value.resolve()?),
+        };
+        let mut value = HeaderValue::from_str(&value)?;
+        value.set_sensitive(true);
+        Ok((name, value))
+    }
+}
+
+/// Resolves the secrets of the given credentials into request headers. They're all marked
+/// sensitive, which is how `utils::send` knows not to follow a redirect to another host with them.
+pub fn headers(auth: &[Auth]) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    for auth in auth {
+        let (name, value) = auth.header()?;
+        headers.insert(name, value);
+    }
+    Ok(headers)
+}
+
+impl FromStr for Auth {
+    type Err = anyhow::Error;
+
+    /// Parses `basic:USERNAME:SECRET`, `bearer:SECRET` or `header:NAME:SECRET`
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || {
+            anyhow!(
+                "'{}' should look like basic:USERNAME:SECRET, bearer:SECRET or header:NAME:SECRET",
+                s
+            )
+        };
+        let (kind, rest) = s.split_once(':').ok_or_else(invalid)?;
+        match kind {
+            "basic" => {
+                let (username, password) = rest.split_once(':').ok_or_else(invalid)?;
+                Ok(Auth::Basic {
+                    username: username.to_string(),
+                    password: password.parse()?,
+                })
+            }
+            "bearer" => Ok(Auth::Bearer {
+                token: rest.parse()?,
+            }),
+            "header" => {
+                let (name, value) = rest.split_once(':').ok_or_else(invalid)?;
+                HeaderName::from_str(name)?;
+                Ok(Auth::Header {
+                    name: name.to_string(),
+                    value: value.parse()?,
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Auth::Basic { username, password } => write!(f, "basic:{}:{}", username, password),
+            Auth::Bearer { token } => write!(f, "bearer:{}", token),
+            Auth::Header { name, value } => write!(f, "header:{}:{}", name, value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_auth() {
+        for spec in &[
+            "basic:me:env:PODCAST_PASSWORD",
+            "bearer:cmd:pass show podcast/foo",
+            "header:X-Api-Key:env:PODCAST_KEY",
+        ] {
+            assert_eq!(&spec.parse::<Auth>().unwrap().to_string(), spec);
+        }
+        assert!("basic:me".parse::<Auth>().is_err());
+        assert!("bearer:plaintext".parse::<Auth>().is_err());
+        assert!("header:Bad Name:env:X".parse::<Auth>().is_err());
+    }
+
+    #[test]
+    fn test_headers() {
+        std::env::set_var("PODCAST_TEST_AUTH_SECRET", "hunter2");
+        let auth = vec![
+            "basic:me:env:PODCAST_TEST_AUTH_SECRET".parse().unwrap(),
+            "header:X-Api-Key:cmd:echo key; echo ignored"
+                .parse()
+                .unwrap(),
+        ];
+        let headers = headers(&auth).unwrap();
+        assert_eq!(headers[header::AUTHORIZATION], "Basic bWU6aHVudGVyMg==");
+        assert_eq!(headers["x-api-key"], "key");
+        assert!(headers.values().all(HeaderValue::is_sensitive));
+    }
+}
@@ -14,5 +14,5 @@ struct Package {
 
 pub fn get_cli_version() -> Result<String> {
     let parsed_cli_toml: Config = toml::from_str(PACKAGE_TOML)?;
-    return Ok(parsed_cli_toml.package.version);
+    Ok(parsed_cli_toml.package.version)
 }
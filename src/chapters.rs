@@ -0,0 +1,83 @@
+use crate::utils;
+use anyhow::Result;
+
+use std::path::{Path, PathBuf};
+
+/// A Podcasting 2.0 JSON chapters file (https://github.com/Podcastindex-org/podcast-namespace/blob/main/chapters/jsonChapters.md)
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Chapters {
+    pub chapters: Vec<Chapter>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Chapter {
+    pub start_time: f64,
+    pub title: Option<String>,
+    /// Chapters that are only there to change the artwork aren't listed in the table of contents
+    #[serde(default = "listed")]
+    pub toc: bool,
+}
+
+fn listed() -> bool {
+    true
+}
+
+impl Chapters {
+    pub fn parse(content: &[u8]) -> Result<Chapters> {
+        let mut chapters: Chapters = serde_json::from_slice(content)?;
+        chapters
+            .chapters
+            .sort_by(|a, b| a.start_time.total_cmp(&b.start_time));
+        Ok(chapters)
+    }
+
+    /// One line per listed chapter, with its start time and title
+    pub fn lines(&self) -> Vec<String> {
+        self.chapters
+            .iter()
+            .filter(|chapter| chapter.toc)
+            .map(|chapter| {
+                let start = utils::duration_from_secs(chapter.start_time.max(0.0))
+                    .map(utils::format_duration);
+                match (start, &chapter.title) {
+                    (Some(start), Some(title)) => format!("{} {}", start, title),
+                    (Some(start), None) => start,
+                    (None, title) => title.clone().unwrap_or_default(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Where the chapters of the episode saved at the given path are kept
+pub fn path(episode_path: &Path) -> PathBuf {
+    utils::append_to_file_name(episode_path, ".chapters.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chapter_lines() {
+        let json = r#"{
+            "version": "1.2.0",
+            "chapters": [
+                { "startTime": 3723.5, "title": "Outro" },
+                { "startTime": 0, "title": "Intro" },
+                { "startTime": 60, "img": "https://example.com/art.jpg", "toc": false },
+                { "startTime": 62 },
+                { "startTime": 1e300, "title": "Never" }
+            ]
+        }"#;
+        assert_eq!(
+            Chapters::parse(json.as_bytes()).unwrap().lines(),
+            vec!["0:00 Intro", "1:02", "1:02:03 Outro", "Never"]
+        );
+        assert_eq!(
+            path(Path::new("/podcasts/Show/1-Episode.mp3")),
+            Path::new("/podcasts/Show/1-Episode.mp3.chapters.json")
+        );
+    }
+}
@@ -1,4 +1,7 @@
-use crate::{executor, structs::State};
+use crate::{
+    executor,
+    structs::{State, SubscriptionStatus},
+};
 use anyhow::Result;
 use clap::ArgMatches;
 
@@ -10,7 +13,20 @@ pub enum CommandC {
     Search(State, ArgMatches),
     Remove(State, ArgMatches),
     Complete(State, ArgMatches),
-    Refresh(State),
+    Import(State, ArgMatches),
+    Export(State, ArgMatches),
+    Config(State, ArgMatches),
+    Tag(State, ArgMatches),
+    Rename(State, ArgMatches),
+    Info(State, ArgMatches),
+    Chapters(State, ArgMatches),
+    Transcript(State, ArgMatches),
+    Grep(State, ArgMatches),
+    Auth(State, ArgMatches),
+    Pause(State, ArgMatches),
+    Resume(State, ArgMatches),
+    Archive(State, ArgMatches),
+    Refresh(State, ArgMatches),
     Update(State),
     NoMatch(State),
 }
@@ -49,30 +65,84 @@ pub fn parse_command(state: State, matches: ArgMatches) -> CommandC {
                 state,
                 matches.subcommand_matches("completion").unwrap().clone(),
             ),
-            "refresh" => CommandC::Refresh(state),
+            "import" => {
+                CommandC::Import(state, matches.subcommand_matches("import").unwrap().clone())
+            }
+            "export" => {
+                CommandC::Export(state, matches.subcommand_matches("export").unwrap().clone())
+            }
+            "config" => {
+                CommandC::Config(state, matches.subcommand_matches("config").unwrap().clone())
+            }
+            "tag" => CommandC::Tag(state, matches.subcommand_matches("tag").unwrap().clone()),
+            "info" => CommandC::Info(state, matches.subcommand_matches("info").unwrap().clone()),
+            "chapters" => CommandC::Chapters(
+                state,
+                matches.subcommand_matches("chapters").unwrap().clone(),
+            ),
+            "transcript" => CommandC::Transcript(
+                state,
+                matches.subcommand_matches("transcript").unwrap().clone(),
+            ),
+            "grep" => CommandC::Grep(state, matches.subcommand_matches("grep").unwrap().clone()),
+            "auth" => CommandC::Auth(state, matches.subcommand_matches("auth").unwrap().clone()),
+            "rename" => {
+                CommandC::Rename(state, matches.subcommand_matches("rename").unwrap().clone())
+            }
+            "pause" => CommandC::Pause(state, matches.subcommand_matches("pause").unwrap().clone()),
+            "resume" => {
+                CommandC::Resume(state, matches.subcommand_matches("resume").unwrap().clone())
+            }
+            "archive" => CommandC::Archive(
+                state,
+                matches.subcommand_matches("archive").unwrap().clone(),
+            ),
+            "refresh" => CommandC::Refresh(
+                state,
+                matches.subcommand_matches("refresh").unwrap().clone(),
+            ),
             "update" => CommandC::Update(state),
             _ => CommandC::NoMatch(state),
         })
         .unwrap_or_else(|| CommandC::NoMatch(state_copy))
 }
 
-pub async fn run_command(command: CommandC) -> Result<State> {
-    match command {
-        CommandC::Download(state, matches) => executor::download(state, &matches).await,
-        CommandC::List(state, matches) => executor::list(state, &matches),
-        CommandC::Play(state, matches) => executor::play(state, &matches),
-        CommandC::Subscribe(state, matches) => executor::subscribe(state, &matches).await,
-        CommandC::Search(state, matches) => executor::search(state, &matches).await,
-        CommandC::Remove(state, matches) => executor::remove(state, &matches),
-        CommandC::Complete(state, matches) => executor::complete(state, &matches),
-        CommandC::Refresh(mut state) => {
-            state.update_rss().await?;
-            Ok(state)
+/// Runs the command, returning the new state along with whether every download the
+/// command started succeeded
+pub async fn run_command(command: CommandC) -> Result<(State, bool)> {
+    let state = match command {
+        CommandC::Download(state, matches) => return executor::download(state, &matches).await,
+        CommandC::Subscribe(state, matches) => return executor::subscribe(state, &matches).await,
+        CommandC::Search(state, matches) => return executor::search(state, &matches).await,
+        CommandC::Import(state, matches) => return executor::import(state, &matches).await,
+        CommandC::Refresh(state, matches) => return executor::refresh(state, &matches).await,
+        CommandC::List(state, matches) => executor::list(state, &matches)?,
+        CommandC::Play(state, matches) => executor::play(state, &matches)?,
+        CommandC::Remove(state, matches) => executor::remove(state, &matches)?,
+        CommandC::Complete(state, matches) => executor::complete(state, &matches)?,
+        CommandC::Export(state, matches) => executor::export(state, &matches)?,
+        CommandC::Config(state, matches) => executor::config(state, &matches)?,
+        CommandC::Tag(state, matches) => executor::tag(state, &matches)?,
+        CommandC::Rename(state, matches) => executor::rename(state, &matches)?,
+        CommandC::Info(state, matches) => executor::info(state, &matches)?,
+        CommandC::Chapters(state, matches) => executor::chapters(state, &matches).await?,
+        CommandC::Transcript(state, matches) => executor::transcript(state, &matches).await?,
+        CommandC::Grep(state, matches) => executor::grep(state, &matches)?,
+        CommandC::Auth(state, matches) => executor::auth(state, &matches)?,
+        CommandC::Pause(state, matches) => {
+            executor::set_status(state, &matches, SubscriptionStatus::Paused)?
+        }
+        CommandC::Resume(state, matches) => {
+            executor::set_status(state, &matches, SubscriptionStatus::Active)?
+        }
+        CommandC::Archive(state, matches) => {
+            executor::set_status(state, &matches, SubscriptionStatus::Archived)?
         }
         CommandC::Update(state) => {
             state.check_for_update().await?;
-            Ok(state)
+            state
         }
-        CommandC::NoMatch(state) => Ok(state),
-    }
+        CommandC::NoMatch(state) => state,
+    };
+    Ok((state, true))
 }
@@ -1,52 +1,222 @@
+use crate::chapters::{self, Chapters};
 use crate::structs::*;
-use crate::utils;
+use crate::{transcript, utils, verify};
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::{self};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use regex::Regex;
-use reqwest::{self, header};
+use reqwest::{self, header, Method, StatusCode, Url};
 use tokio::io::AsyncWriteExt;
+use tokio::sync::watch;
+
+/// How many episodes are downloaded at once, unless configured otherwise
+pub(crate) const CONCURRENT_DOWNLOADS: usize = 4;
+/// How many of those may come from the same host
+pub(crate) const DOWNLOADS_PER_HOST: usize = 2;
+
+/// Writes the episode from the first of its sources that works, returning where it was saved.
+/// The file extension follows the source that was used.
+async fn fetch_episode(
+    client: &reqwest::Client,
+    episode: &Download,
+    scheduler: &Scheduler,
+    pb: &ProgressBar,
+) -> Result<PathBuf> {
+    let mut sources = episode.sources.iter().peekable();
+    while let Some(source) = sources.next() {
+        let path = episode.path_for(source);
+        // A retry continues from what the failed attempt left in the `.part` file
+        let fetch = || fetch_source(client, episode, source, &path, &scheduler.throttle, pb);
+        match utils::with_retries(scheduler.retries, fetch).await {
+            Ok(()) => return Ok(path),
+            Err(err) if sources.peek().is_some() => {
+                pb.println(format!(
+                    "Couldn't download {} from {}: {}\nTrying the next source...",
+                    episode.title, source.url, err
+                ));
+                // What we have of this source can't be resumed from another one
+                let part = part_path(&path);
+                if part.exists() {
+                    tokio::fs::remove_file(&part).await?;
+                }
+                pb.set_position(0);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Err(anyhow!("{} has nothing to download", episode.title))
+}
 
-/// This handles downloading a single episode
-///
-/// Not to be used in conjunction with download_multiple_episodes
-async fn download_episode(pb: ProgressBar, episode: Download) -> Result<()> {
-    let title = truncate_title(&episode.title);
-    pb.set_message(title);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("[{eta_precise}] {msg} [{bytes_per_sec}] [{bytes}/{total_bytes}]"),
-    );
-    let client = reqwest::Client::new();
-    let mut request = client.get(&episode.url);
-    if episode.path.exists() {
-        let size = episode.path.metadata()?.len() - 1;
-        request = request.header(header::RANGE, format!("bytes={}-", size));
-        pb.inc(size);
+/// Writes one of the episode's sources to the given path. It's downloaded to a `.part` file
+/// first and only renamed into place once complete, so an interrupted download is resumed
+/// rather than taken for a finished one. Sources with a `file://` URL are copied.
+async fn fetch_source(
+    client: &reqwest::Client,
+    episode: &Download,
+    source: &Source,
+    path: &Path,
+    throttle: &Throttle,
+    pb: &ProgressBar,
+) -> Result<()> {
+    let part = part_path(path);
+    if let Some(file) = utils::file_path(&source.url) {
+        let copied = tokio::fs::copy(&file, &part).await?;
+        pb.inc(copied);
+        return finish(episode, source, &part, path, Some(copied), None).await;
     }
+
+    let offset = match tokio::fs::metadata(&part).await {
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    };
+    let mut headers = episode.headers.clone();
+    if 0 < offset {
+        headers.insert(header::RANGE, format!("bytes={}-", offset).parse()?);
+    }
+    let mut download = utils::send(client, Method::GET, &source.url, headers).await?;
+    // Only a partial response starting right where we stopped continues the file
+    let resumed = 0 < offset
+        && download.status() == StatusCode::PARTIAL_CONTENT
+        && range_start(download.headers()) == Some(offset);
+    if 0 < offset && !resumed && download.status() != StatusCode::OK {
+        download = utils::send(client, Method::GET, &source.url, episode.headers.clone()).await?;
+    }
+    let mut download = utils::check_status(download)?;
+    // What the server says it's sending is the size to check against, unlike the HEAD
+    // request or the feed, which may be out of date or differ for every request
+    let size = if resumed {
+        range_total(download.headers())
+    } else {
+        download.content_length()
+    };
+    let content_type = download
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
+
+    // Anything but a resumed download starts over from the beginning
     let mut dest = tokio::io::BufWriter::new(
         tokio::fs::OpenOptions::new()
             .create(true)
-            .append(true)
-            .open(&episode.path)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(&part)
             .await?,
     );
+    pb.set_position(if resumed { offset } else { 0 });
 
-    let mut download = request.send().await?;
+    let host = url_host(&source.url);
     while let Some(chunk) = download.chunk().await? {
-        let written = dest.write(&chunk).await?;
-        pb.inc(written as u64);
+        dest.write_all(&chunk).await?;
+        throttle.take(&host, chunk.len()).await;
+        pb.inc(chunk.len() as u64);
         let title = truncate_title(&episode.title);
         pb.set_message(title);
     }
     dest.flush().await?;
-    pb.finish_with_message("Done");
+    finish(episode, source, &part, path, size, content_type.as_deref()).await
+}
+
+/// Moves a complete download into place once it checks out against the sizes the download,
+/// the HEAD request and the feed gave, and is the media the feed promised. One that doesn't
+/// is removed, since it's no use resuming either.
+async fn finish(
+    episode: &Download,
+    source: &Source,
+    part: &Path,
+    path: &Path,
+    size: Option<u64>,
+    content_type: Option<&str>,
+) -> Result<()> {
+    // What the HEAD request found is only known for the first source
+    let head = match episode.sources.first() {
+        Some(first) if first == source && 0 < episode.size => Some(episode.size),
+        _ => None,
+    };
+    let checked = verify::check_size(
+        part,
+        &[
+            (size, "the download"),
+            (head, "the server"),
+            (source.length, "the feed"),
+        ],
+    )
+    .and_then(|_| verify::check(part, source.mime_type.as_deref(), content_type));
+    if let Err(err) = checked {
+        tokio::fs::remove_file(part).await?;
+        return Err(err);
+    }
+    tokio::fs::rename(part, path).await?;
     Ok(())
 }
 
+/// Where an episode is downloaded to until it's complete
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// Where the body of a partial response starts, from its `Content-Range: bytes START-END/SIZE`
+fn range_start(headers: &header::HeaderMap) -> Option<u64> {
+    let range = headers.get(header::CONTENT_RANGE)?.to_str().ok()?;
+    range
+        .trim()
+        .strip_prefix("bytes ")?
+        .split('-')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// The size of the whole file a partial response is part of, from its `Content-Range`
+fn range_total(headers: &header::HeaderMap) -> Option<u64> {
+    let range = headers.get(header::CONTENT_RANGE)?.to_str().ok()?;
+    range.rsplit('/').next()?.trim().parse().ok()
+}
+
+/// Saves the chapters and transcript of an episode, if it has them, next to the path it's
+/// looked up by whichever source it came from. The episode itself is fine without them,
+/// so failures are only reported.
+async fn download_extras(client: &reqwest::Client, episode: &Download, pb: &ProgressBar) {
+    if let Some(url) = &episode.chapters {
+        let result = async {
+            let content = utils::fetch(client, url, episode.headers.clone()).await?;
+            Chapters::parse(&content)?;
+            tokio::fs::write(chapters::path(&episode.path), &content).await?;
+            Ok::<_, anyhow::Error>(())
+        };
+        if let Err(err) = result.await {
+            pb.println(format!(
+                "Couldn't download chapters for {}: {}",
+                episode.title, err
+            ));
+        }
+    }
+    if let Some(link) = &episode.transcript {
+        let result = async {
+            let content = utils::fetch(client, &link.url, episode.headers.clone()).await?;
+            tokio::fs::write(transcript::path(&episode.path, link.format), &content).await?;
+            Ok::<_, anyhow::Error>(())
+        };
+        if let Err(err) = result.await {
+            pb.println(format!(
+                "Couldn't download transcript for {}: {}",
+                episode.title, err
+            ));
+        }
+    }
+}
+
 fn truncate_title(title: &str) -> String {
     let fix_char_len = 45;
     let mut title = title.to_owned();
@@ -63,71 +233,239 @@ fn truncate_title(title: &str) -> String {
     title
 }
 
-/// Handles downloading a list of episodes on a single thread
-async fn download_multiple_episodes(pb: ProgressBar, episodes: Vec<Download>) -> Result<()> {
-    let client = reqwest::Client::new();
-    for (index, episode) in episodes.iter().enumerate() {
-        let title = truncate_title(&episode.title);
+/// Downloads waiting to start, and how many are running from each host
+struct Queue {
+    waiting: VecDeque<(usize, Download)>,
+    running: HashMap<String, usize>,
+}
+
+impl Queue {
+    /// Takes the first waiting download whose host isn't at its limit yet
+    fn next(&mut self, per_host: usize) -> Option<(usize, Download)> {
+        let index = self.waiting.iter().position(|(_, download)| {
+            self.running.get(&host(download)).copied().unwrap_or(0) < per_host
+        })?;
+        let next = self.waiting.remove(index)?;
+        *self.running.entry(host(&next.1)).or_default() += 1;
+        Some(next)
+    }
+
+    fn finish(&mut self, download: &Download) {
+        if let Some(running) = self.running.get_mut(&host(download)) {
+            *running -= 1;
+        }
+    }
+}
+
+/// The host an episode is downloaded from, to limit how many downloads it gets at once
+fn host(download: &Download) -> String {
+    download
+        .sources
+        .first()
+        .map(|source| url_host(&source.url))
+        .unwrap_or_default()
+}
+
+fn url_host(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(String::from))
+        .unwrap_or_default()
+}
+
+/// Spreads a number of bytes per second across everything downloading through it
+struct RateLimit {
+    bytes_per_sec: u64,
+    /// When the bytes downloaded so far will have been within the limit
+    until: Mutex<Instant>,
+}
+
+impl RateLimit {
+    fn new(bytes_per_sec: u64) -> Self {
+        RateLimit {
+            bytes_per_sec,
+            until: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Accounts for downloaded bytes, returning when downloading may continue
+    fn take(&self, bytes: usize) -> Instant {
+        let mut until = self.until.lock().unwrap();
+        // Time that went unused isn't saved up, or a pause would be followed by a burst
+        let start = (*until).max(Instant::now());
+        *until = start + Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64);
+        *until
+    }
+}
+
+/// The download rate limits, in total and for each host
+struct Throttle {
+    total: Option<RateLimit>,
+    per_host: HashMap<String, RateLimit>,
+}
+
+impl Throttle {
+    fn new(config: &Config, episodes: &[Download]) -> Result<Self> {
+        let total = config.max_download_rate.as_deref().map(utils::parse_rate);
+        let per_host = config
+            .max_download_rate_per_host
+            .as_deref()
+            .map(utils::parse_rate)
+            .transpose()?;
+        Ok(Throttle {
+            total: total.transpose()?.map(RateLimit::new),
+            per_host: per_host
+                .map(|rate| {
+                    episodes
+                        .iter()
+                        .flat_map(|episode| &episode.sources)
+                        .map(|source| (url_host(&source.url), RateLimit::new(rate)))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Waits as long as the limits require after downloading some bytes from a host
+    async fn take(&self, host: &str, bytes: usize) {
+        let until = self
+            .total
+            .iter()
+            .chain(self.per_host.get(host))
+            .map(|limit| limit.take(bytes))
+            .max();
+        if let Some(until) = until {
+            tokio::time::sleep_until(until.into()).await;
+        }
+    }
+}
+
+/// What the download workers share
+struct Scheduler {
+    queue: Mutex<Queue>,
+    per_host: usize,
+    retries: u32,
+    throttle: Throttle,
+    total: usize,
+    /// Counts finished downloads, to wake workers waiting for a busy host
+    finished: watch::Sender<usize>,
+}
+
+/// Takes downloads off the queue one at a time until it's empty
+async fn download_worker(
+    pb: ProgressBar,
+    scheduler: Arc<Scheduler>,
+    mut on_finish: watch::Receiver<usize>,
+) -> Vec<(usize, String, Result<PathBuf>)> {
+    let client = utils::client();
+    let mut results = vec![];
+    loop {
+        on_finish.borrow_and_update();
+        let next = {
+            let mut queue = scheduler.queue.lock().unwrap();
+            if queue.waiting.is_empty() {
+                break;
+            }
+            queue.next(scheduler.per_host)
+        };
+        let (index, episode) = match next {
+            Some(next) => next,
+            // Everything left is from a host that's busy, so wait for a download to finish
+            None => {
+                on_finish.changed().await.ok();
+                continue;
+            }
+        };
 
         pb.set_position(0);
         pb.set_length(episode.size);
-        pb.set_message(title);
+        pb.set_message(truncate_title(&episode.title));
         pb.set_style(ProgressStyle::default_bar().template(
-            &(format!("[{}/{}]", index + 1, episodes.len())
+            &(format!("[{}/{}]", index + 1, scheduler.total)
                 + " [{eta_precise}] {msg} [{bytes_per_sec}] [{bytes}/{total_bytes}]"),
         ));
-        let mut request = client.get(&episode.url);
-        if episode.path.exists() {
-            let size = episode.path.metadata()?.len() - 1;
-            request = request.header(header::RANGE, format!("bytes={}-", size));
-            pb.inc(size);
-        }
-        let mut dest = tokio::io::BufWriter::new(
-            tokio::fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(&episode.path)
-                .await?,
-        );
+        let result = match fetch_episode(&client, &episode, &scheduler, &pb).await {
+            Ok(path) => {
+                download_extras(&client, &episode, &pb).await;
+                Ok(path)
+            }
+            Err(err) => Err(err),
+        };
 
-        let mut download = request.send().await?;
-        while let Some(chunk) = download.chunk().await? {
-            let written = dest.write(&chunk).await?;
-            pb.inc(written as u64);
-            let title = truncate_title(&episode.title);
-            pb.set_message(title);
-        }
-        dest.flush().await?;
+        scheduler.queue.lock().unwrap().finish(&episode);
+        scheduler.finished.send_modify(|count| *count += 1);
+        results.push((index, episode.title, result));
     }
-    pb.finish_with_message("Done");
-    Ok(())
+    pb.finish_and_clear();
+    results
 }
 
-/// Splits the given list optimally across available threads and downloads them pretty
-pub async fn download_episodes(episodes: Vec<Download>) -> Result<()> {
+/// Downloads the episodes a few at a time, as many as the config allows overall and per host,
+/// then reports how each one went. A failed download doesn't stop the others. Returns
+/// whether each episode was downloaded, in the order given.
+pub async fn download_episodes(episodes: Vec<Download>, config: &Config) -> Result<Vec<bool>> {
     if episodes.is_empty() {
-        return Ok(());
+        return Ok(vec![]);
     }
 
+    let total = episodes.len();
+    let workers = config
+        .max_concurrent_downloads
+        .unwrap_or(CONCURRENT_DOWNLOADS)
+        .clamp(1, total);
+    let per_host = config
+        .max_downloads_per_host
+        .unwrap_or(DOWNLOADS_PER_HOST)
+        .max(1);
+    let throttle = Throttle::new(config, &episodes)?;
+    let (finished, on_finish) = watch::channel(0);
+    let scheduler = Arc::new(Scheduler {
+        queue: Mutex::new(Queue {
+            waiting: episodes.into_iter().enumerate().collect(),
+            running: HashMap::new(),
+        }),
+        per_host,
+        retries: config.retries(),
+        throttle,
+        total,
+        finished,
+    });
+
     let mp = MultiProgress::new();
-    let num_cpus = num_cpus::get();
-    if episodes.len() < num_cpus {
-        for episode in episodes {
-            let pb = mp.add(ProgressBar::new(episode.size));
-            tokio::spawn(download_episode(pb, episode.to_owned()));
+    let handles: Vec<_> = (0..workers)
+        .map(|_| {
+            tokio::spawn(download_worker(
+                mp.add(ProgressBar::new(0)),
+                scheduler.clone(),
+                on_finish.clone(),
+            ))
+        })
+        .collect();
+    mp.join_and_clear()?;
+
+    let mut results = vec![];
+    for handle in handles {
+        results.extend(handle.await?);
+    }
+    results.sort_by_key(|(index, _, _)| *index);
+
+    let mut failed = 0;
+    for (_, title, result) in &results {
+        match result {
+            Ok(_) => println!("Downloaded {}", title),
+            Err(err) => {
+                failed += 1;
+                println!("Failed to download {}: {:#}", title, err);
+            }
         }
-        mp.join_and_clear()?;
-        return Ok(());
     }
-
-    let chunk_size = episodes.len() / num_cpus;
-    for chunk in episodes.chunks(chunk_size) {
-        let pb = mp.add(ProgressBar::new(0));
-        let cp = chunk.to_vec();
-        tokio::spawn(download_multiple_episodes(pb, cp.to_owned()));
+    if 0 < failed {
+        println!("{} of {} downloads failed", failed, total);
     }
-    mp.join_and_clear()?;
-    Ok(())
+    Ok(results
+        .iter()
+        .map(|(_, _, result)| result.is_ok())
+        .collect())
 }
 
 pub async fn download_range(
@@ -141,15 +479,13 @@ pub async fn download_range(
         if re_pod.is_match(&subscription.title) {
             let podcast = Podcast::from_title(&subscription.title)?;
 
-            let mut path = utils::get_podcast_dir()?;
-            path.push(podcast.title());
-            utils::create_dir_if_not_exist(&path)?;
+            utils::create_dir_if_not_exist(&subscription.download_dir()?)?;
 
             let episodes = podcast.episodes();
             let episodes_to_download = parse_download_episodes(e_search)?;
             for ep_num in episodes_to_download {
                 let episode = &episodes[episodes.len() - ep_num];
-                if let Some(ep) = Download::new(state, &podcast, episode).await? {
+                if let Some(ep) = Download::new(state, subscription, &podcast, episode).await? {
                     downloads.push(ep);
                 }
             }
@@ -169,9 +505,7 @@ pub async fn download_matching(
         if re_pod.is_match(&subscription.title) {
             let podcast = Podcast::from_title(&subscription.title)?;
 
-            let mut path = utils::get_podcast_dir()?;
-            path.push(podcast.title());
-            utils::create_dir_if_not_exist(&path)?;
+            utils::create_dir_if_not_exist(&subscription.download_dir()?)?;
 
             let episodes = podcast.episodes();
             let episodes_to_download: Vec<&Episode> = episodes
@@ -180,7 +514,7 @@ pub async fn download_matching(
                 .collect();
 
             for episode in episodes_to_download {
-                if let Some(ep) = Download::new(state, &podcast, episode).await? {
+                if let Some(ep) = Download::new(state, subscription, &podcast, episode).await? {
                     downloads.push(ep);
                 }
             }
@@ -189,12 +523,15 @@ pub async fn download_matching(
     Ok(downloads)
 }
 
-fn find_matching_podcast(state: &State, p_search: &str) -> Result<Option<Podcast>> {
+fn find_matching_podcast<'a>(
+    state: &'a State,
+    p_search: &str,
+) -> Result<Option<(&'a Subscription, Podcast)>> {
     let re_pod = Regex::new(&format!("(?i){}", &p_search))?;
     for subscription in &state.subscriptions {
         if re_pod.is_match(&subscription.title) {
             let podcast = Podcast::from_title(&subscription.title)?;
-            return Ok(Some(podcast));
+            return Ok(Some((subscription, podcast)));
         }
     }
     Ok(None)
@@ -212,8 +549,13 @@ pub async fn download_episode_by_num(
             if re_pod.is_match(&subscription.title) {
                 let podcast = Podcast::from_title(&subscription.title)?;
                 let episodes = podcast.episodes();
-                if let Some(ep) =
-                    Download::new(state, &podcast, &episodes[episodes.len() - ep_num]).await?
+                if let Some(ep) = Download::new(
+                    state,
+                    subscription,
+                    &podcast,
+                    &episodes[episodes.len() - ep_num],
+                )
+                .await?
                 {
                     downloads.push(ep);
                 }
@@ -253,13 +595,13 @@ pub async fn download_episode_by_name(
 
             if download_all {
                 for episode in filtered_episodes {
-                    if let Some(ep) = Download::new(state, &podcast, episode).await? {
+                    if let Some(ep) = Download::new(state, subscription, &podcast, episode).await? {
                         downloads.push(ep);
                     }
                 }
             } else {
                 for episode in filtered_episodes.take(1) {
-                    if let Some(ep) = Download::new(state, &podcast, episode).await? {
+                    if let Some(ep) = Download::new(state, subscription, &podcast, episode).await? {
                         downloads.push(ep);
                     }
                 }
@@ -287,17 +629,14 @@ pub async fn download_all(state: &State, p_search: &str) -> Result<Vec<Download>
                 continue;
             }
 
-            let mut path = utils::get_podcast_dir()?;
-            path.push(podcast.title());
-
-            if let Ok(downloaded) = utils::already_downloaded(podcast.title()) {
+            if let Ok(downloaded) = utils::already_downloaded(&subscription.download_dir()?) {
                 let episodes = podcast.episodes();
                 for e in episodes
                     .iter()
                     .filter(|e| e.title().is_some())
                     .filter(|e| !downloaded.contains(&e.title().unwrap()))
                 {
-                    if let Some(ep) = Download::new(state, &podcast, e).await? {
+                    if let Some(ep) = Download::new(state, subscription, &podcast, e).await? {
                         downloads.push(ep);
                     }
                 }
@@ -313,10 +652,10 @@ pub async fn download_latest(
     latest: usize,
 ) -> Result<Vec<Download>> {
     let mut downloads = vec![];
-    if let Some(podcast) = find_matching_podcast(state, p_search)? {
+    if let Some((subscription, podcast)) = find_matching_podcast(state, p_search)? {
         let episodes = podcast.episodes();
         for episode in &episodes[..latest] {
-            if let Some(ep) = Download::new(state, &podcast, episode).await? {
+            if let Some(ep) = Download::new(state, subscription, &podcast, episode).await? {
                 downloads.push(ep);
             }
         }
@@ -324,9 +663,13 @@ pub async fn download_latest(
     Ok(downloads)
 }
 
-pub async fn download_rss(state: &State, url: &str) -> Result<Vec<Download>> {
-    let channel = utils::download_rss_feed(url).await?;
-    let mut download_limit = state.config.auto_download_limit.unwrap_or(1) as usize;
+/// The latest episodes of a new subscription, as many as the auto-download limit allows
+pub async fn download_rss(
+    state: &State,
+    subscription: &Subscription,
+    podcast: &Podcast,
+) -> Result<Vec<Download>> {
+    let mut download_limit = subscription.auto_download_limit(&state.config) as usize;
     let mut downloads = vec![];
 
     if 0 < download_limit {
@@ -334,14 +677,13 @@ pub async fn download_rss(state: &State, url: &str) -> Result<Vec<Download>> {
             "Subscribe auto-download limit set to: {}\nDownloading episode(s)...",
             download_limit
         );
-        let podcast = Podcast::from(channel);
         let episodes = podcast.episodes();
         if episodes.len() < download_limit {
             download_limit = episodes.len()
         }
 
         for episode in episodes[..download_limit].iter() {
-            if let Some(ep) = Download::new(state, &podcast, episode).await? {
+            if let Some(ep) = Download::new(state, subscription, podcast, episode).await? {
                 downloads.push(ep);
             }
         }
@@ -374,3 +716,101 @@ fn parse_download_episodes(e_search: &str) -> Result<HashSet<usize>> {
     }
     Ok(elements)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn download(url: &str) -> Download {
+        Download {
+            id: url.into(),
+            title: url.into(),
+            path: PathBuf::new(),
+            sources: vec![Source {
+                url: url.into(),
+                mime_type: None,
+                length: None,
+                bitrate: None,
+            }],
+            size: 0,
+            headers: header::HeaderMap::new(),
+            chapters: None,
+            transcript: None,
+        }
+    }
+
+    #[test]
+    fn test_partial_downloads() {
+        assert_eq!(
+            part_path(Path::new("/podcasts/Show/1-Episode.mp3")),
+            Path::new("/podcasts/Show/1-Episode.mp3.part")
+        );
+
+        let mut headers = header::HeaderMap::new();
+        assert_eq!(range_start(&headers), None);
+        headers.insert(
+            header::CONTENT_RANGE,
+            "bytes 1000-1999/2000".parse().unwrap(),
+        );
+        assert_eq!(range_start(&headers), Some(1000));
+        assert_eq!(range_total(&headers), Some(2000));
+        headers.insert(header::CONTENT_RANGE, "bytes 0-999/*".parse().unwrap());
+        assert_eq!(range_total(&headers), None);
+        headers.insert(header::CONTENT_RANGE, "bytes */2000".parse().unwrap());
+        assert_eq!(range_start(&headers), None);
+    }
+
+    #[test]
+    fn test_rate_limit_is_shared() {
+        let limit = RateLimit::new(1000);
+        let start = Instant::now();
+        limit.take(500);
+        let until = limit.take(1500);
+        assert!(until >= start + Duration::from_secs(2));
+        assert!(until < start + Duration::from_millis(2100));
+    }
+
+    #[tokio::test]
+    async fn test_throttle_applies_total_and_host_limits() {
+        let throttle = Throttle {
+            total: Some(RateLimit::new(2000)),
+            per_host: vec![
+                ("a.com".to_string(), RateLimit::new(1000)),
+                ("b.com".to_string(), RateLimit::new(4000)),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let start = Instant::now();
+        // The host limit is the stricter one here
+        throttle.take("a.com", 400).await;
+        assert!(start.elapsed() >= Duration::from_millis(400));
+        // b.com's own limit would allow it by 500ms, but the total limit still counts a.com
+        throttle.take("b.com", 400).await;
+        assert!(start.elapsed() >= Duration::from_millis(600));
+    }
+
+    #[test]
+    fn test_queue_limits_downloads_per_host() {
+        let mut queue = Queue {
+            waiting: [
+                "https://a.example.com/1.mp3",
+                "https://a.example.com/2.mp3",
+                "https://b.example.com/3.mp3",
+            ]
+            .iter()
+            .map(|url| download(url))
+            .enumerate()
+            .collect(),
+            running: HashMap::new(),
+        };
+        let (first, a) = queue.next(1).unwrap();
+        assert_eq!(first, 0);
+        assert_eq!(queue.next(1).unwrap().0, 2);
+        assert!(queue.next(1).is_none());
+
+        queue.finish(&a);
+        assert_eq!(queue.next(1).unwrap().0, 1);
+        assert!(queue.waiting.is_empty());
+    }
+}
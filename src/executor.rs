@@ -1,90 +1,100 @@
 use crate::actions::*;
+use crate::auth::Auth;
 use crate::download;
+use crate::opml;
 use crate::playback;
 use crate::{structs::*, utils};
 use anyhow::Result;
 use clap::ArgMatches;
 use download::download_episodes;
-use indicatif::ProgressBar;
 use regex::Regex;
 use std::env;
 use std::{
-    io::{self, Read, Write},
+    fs::File,
+    io::{self, BufReader, BufWriter, IsTerminal, Write},
     path::Path,
 };
 
-struct DownloadProgress<R> {
-    inner: R,
-    progress_bar: ProgressBar,
-}
-
-impl<R: Read> Read for DownloadProgress<R> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.inner.read(buf).map(|n| {
-            self.progress_bar.inc(n as u64);
-            n
-        })
-    }
-}
-
-pub async fn download(state: State, matches: &ArgMatches) -> Result<State> {
+/// Downloads the episodes asked for, returning the state along with whether every one of
+/// them was downloaded
+pub async fn download(state: State, matches: &ArgMatches) -> Result<(State, bool)> {
     let mut mutable_state = state.clone();
-    let podcast = matches.get_one::<String>("PODCAST").unwrap();
+    let podcast = matches.get_one::<String>("PODCAST");
+    let searches = match matches.get_one::<String>("TAG") {
+        Some(tag) => tagged_podcasts(&state, tag, podcast.map(String::as_str))?,
+        None => vec![podcast.unwrap().to_string()],
+    };
     if let Some(template) = matches.get_one::<String>("TEMPLATE") {
         mutable_state.config.filename_pattern = Some(template.to_string());
+        // The template given on the command line wins over any subscription's own pattern
+        for sub in &mut mutable_state.subscriptions {
+            sub.config.filename_pattern = None;
+        }
     }
+    limit_rate(&mut mutable_state.config, matches)?;
     let mut to_download = vec![];
-    if let Some(pattern) = matches.get_one::<String>("PATTERN") {
-        let regex = Regex::new(pattern)?;
-        to_download.append(&mut download::download_matching(&mutable_state, podcast, &regex).await?)
-    } else {
-        match matches.get_one::<String>("EPISODE") {
-            Some(ep) => {
-                if String::from(ep).contains(|c| c == '-' || c == ',') {
-                    to_download
-                        .append(&mut download::download_range(&mutable_state, podcast, ep).await?);
-                } else if matches.occurrences_of("NAME") > 0 {
-                    to_download.append(
-                        &mut download::download_episode_by_name(
-                            &mutable_state,
-                            podcast,
-                            ep,
-                            0 < matches.occurrences_of("ALL"),
-                        )
-                        .await?,
-                    );
-                } else {
-                    to_download.append(
-                        &mut download::download_episode_by_num(&mutable_state, podcast, ep).await?,
-                    );
+    for podcast in &searches {
+        let podcast = podcast.as_str();
+        if let Some(pattern) = matches.get_one::<String>("PATTERN") {
+            let regex = Regex::new(pattern)?;
+            to_download
+                .append(&mut download::download_matching(&mutable_state, podcast, &regex).await?)
+        } else {
+            match matches.get_one::<String>("EPISODE") {
+                Some(ep) => {
+                    if String::from(ep).contains(['-', ',']) {
+                        to_download.append(
+                            &mut download::download_range(&mutable_state, podcast, ep).await?,
+                        );
+                    } else if matches.occurrences_of("NAME") > 0 {
+                        to_download.append(
+                            &mut download::download_episode_by_name(
+                                &mutable_state,
+                                podcast,
+                                ep,
+                                0 < matches.occurrences_of("ALL"),
+                            )
+                            .await?,
+                        );
+                    } else {
+                        to_download.append(
+                            &mut download::download_episode_by_num(&mutable_state, podcast, ep)
+                                .await?,
+                        );
+                    }
                 }
+                None => match matches.value_of("latest") {
+                    Some(num_of_latest) => {
+                        to_download.append(
+                            &mut download::download_latest(
+                                &mutable_state,
+                                podcast,
+                                num_of_latest.parse()?,
+                            )
+                            .await?,
+                        );
+                    }
+                    None => {
+                        to_download
+                            .append(&mut download::download_all(&mutable_state, podcast).await?);
+                    }
+                },
             }
-            None => match matches.value_of("latest") {
-                Some(num_of_latest) => {
-                    to_download.append(
-                        &mut download::download_latest(
-                            &mutable_state,
-                            podcast,
-                            num_of_latest.parse()?,
-                        )
-                        .await?,
-                    );
-                }
-                None => {
-                    to_download.append(&mut download::download_all(&mutable_state, podcast).await?);
-                }
-            },
         }
     }
 
-    download_episodes(to_download).await?;
-    Ok(state)
+    let downloaded = download_episodes(to_download, &mutable_state.config).await?;
+    Ok((state, downloaded.iter().all(|downloaded| *downloaded)))
 }
 
 pub fn list(state: State, matches: &ArgMatches) -> Result<State> {
     match matches.value_of("PODCAST") {
         Some(regex) => list_episodes(regex)?,
-        None => list_subscriptions(&state)?,
+        None => list_subscriptions(
+            &state,
+            matches.value_of("TAG"),
+            matches.occurrences_of("ALL") > 0,
+        )?,
     }
     Ok(state)
 }
@@ -104,13 +114,162 @@ pub fn play(state: State, matches: &ArgMatches) -> Result<State> {
     Ok(state)
 }
 
-pub async fn subscribe(state: State, matches: &ArgMatches) -> Result<State> {
+pub async fn chapters(state: State, matches: &ArgMatches) -> Result<State> {
+    let podcast = matches.value_of("PODCAST").unwrap();
+    let episode = matches.value_of("EPISODE").unwrap();
+    print_chapters(&state, podcast, episode).await?;
+    Ok(state)
+}
+
+pub async fn transcript(state: State, matches: &ArgMatches) -> Result<State> {
+    let podcast = matches.value_of("PODCAST").unwrap();
+    let episode = matches.value_of("EPISODE").unwrap();
+    print_transcript(&state, podcast, episode).await?;
+    Ok(state)
+}
+
+pub fn grep(state: State, matches: &ArgMatches) -> Result<State> {
+    grep_transcripts(&state, matches.value_of("TEXT").unwrap())?;
+    Ok(state)
+}
+
+pub fn info(state: State, matches: &ArgMatches) -> Result<State> {
+    print_info(&state, matches.value_of("PODCAST").unwrap())?;
+    Ok(state)
+}
+
+pub async fn subscribe(mut state: State, matches: &ArgMatches) -> Result<(State, bool)> {
     let url = matches.value_of("URL").unwrap();
-    sub(state, url).await
+    let auth = parse_auth(matches)?;
+    let saved = state.config.max_download_rate.clone();
+    limit_rate(&mut state.config, matches)?;
+    let (mut state, all_downloaded) = sub(state, url, &auth).await?;
+    state.config.max_download_rate = saved;
+    Ok((state, all_downloaded))
+}
+
+pub async fn refresh(mut state: State, matches: &ArgMatches) -> Result<(State, bool)> {
+    let saved = state.config.max_download_rate.clone();
+    limit_rate(&mut state.config, matches)?;
+    let full = matches.occurrences_of("FULL") > 0;
+    let all_downloaded = state.update_rss(matches.value_of("TAG"), full).await?;
+    state.config.max_download_rate = saved;
+    Ok((state, all_downloaded))
+}
+
+/// Limits the total download rate to what `--limit-rate` asks for. The caller puts back
+/// the configured rate afterwards, so the limit only applies to this run.
+fn limit_rate(config: &mut Config, matches: &ArgMatches) -> Result<()> {
+    if let Some(rate) = matches.get_one::<String>("LIMIT_RATE") {
+        utils::parse_rate(rate)?;
+        config.max_download_rate = Some(rate.to_string());
+    }
+    Ok(())
+}
+
+async fn sub(mut state: State, url: &str, auth: &[Auth]) -> Result<(State, bool)> {
+    // Only ask about a duplicate subscription if someone is there to answer
+    let ask = io::stdin().is_terminal();
+    let (_, all_downloaded) = state
+        .subscribe(&utils::feed_url(url)?, auth, true, ask)
+        .await?;
+    Ok((state, all_downloaded))
 }
 
-async fn sub(mut state: State, url: &str) -> Result<State> {
-    state.subscribe(url).await?;
+fn parse_auth(matches: &ArgMatches) -> Result<Vec<Auth>> {
+    matches
+        .values_of("AUTH")
+        .map(|values| values.map(str::parse).collect())
+        .unwrap_or_else(|| Ok(vec![]))
+}
+
+pub fn auth(mut state: State, matches: &ArgMatches) -> Result<State> {
+    let p_search = matches.value_of("PODCAST").unwrap();
+    let re_pod = Regex::new(&format!("(?i){}", &p_search))?;
+    let auth = parse_auth(matches)?;
+    let sub = match state
+        .subscriptions
+        .iter_mut()
+        .find(|sub| re_pod.is_match(sub.title()))
+    {
+        Some(sub) => sub,
+        None => {
+            eprintln!("No subscription matches {}", p_search);
+            return Ok(state);
+        }
+    };
+
+    if matches.occurrences_of("CLEAR") > 0 {
+        sub.auth.clear();
+    } else if !auth.is_empty() {
+        sub.auth = auth;
+    } else {
+        println!("{}", sub.title());
+        for auth in &sub.auth {
+            println!("  {}", auth);
+        }
+    }
+    Ok(state)
+}
+
+pub async fn import(mut state: State, matches: &ArgMatches) -> Result<(State, bool)> {
+    let path = matches.value_of("FILE").unwrap();
+    let saved = state.config.max_download_rate.clone();
+    limit_rate(&mut state.config, matches)?;
+    let auto_download = matches.occurrences_of("NO_DOWNLOAD") == 0;
+    let outlines = opml::parse(BufReader::new(File::open(path)?))?;
+
+    let mut failed = vec![];
+    let mut all_downloaded = true;
+    for outline in &outlines {
+        println!(
+            "Subscribing to {}",
+            outline.title.as_deref().unwrap_or(&outline.url)
+        );
+        // Podcasts we're already subscribed to are left as they are
+        match state
+            .subscribe(&outline.url, &[], auto_download, false)
+            .await
+        {
+            Ok((index, downloaded)) => {
+                state.subscriptions[index]
+                    .tags
+                    .extend(outline.tags.iter().cloned());
+                all_downloaded &= downloaded;
+            }
+            Err(err) => failed.push((outline, err)),
+        }
+    }
+    state.config.max_download_rate = saved;
+
+    println!(
+        "Imported {} of {} podcasts",
+        outlines.len() - failed.len(),
+        outlines.len()
+    );
+    if !failed.is_empty() {
+        let stderr = io::stderr();
+        let mut handle = stderr.lock();
+        writeln!(&mut handle, "Failed to subscribe to:")?;
+        for (outline, err) in failed {
+            writeln!(&mut handle, "  {} ({:#})", outline.url, err)?;
+        }
+    }
+    Ok((state, all_downloaded))
+}
+
+pub fn export(state: State, matches: &ArgMatches) -> Result<State> {
+    match matches.value_of("OUTPUT") {
+        Some(path) => {
+            opml::write(&state.subscriptions, BufWriter::new(File::create(path)?))?;
+            println!(
+                "Exported {} podcasts to {}",
+                state.subscriptions.len(),
+                path
+            );
+        }
+        None => opml::write(&state.subscriptions, io::stdout().lock())?,
+    }
     Ok(state)
 }
 
@@ -137,6 +296,120 @@ pub fn remove(mut state: State, matches: &ArgMatches) -> Result<State> {
     Ok(state)
 }
 
+pub fn config(mut state: State, matches: &ArgMatches) -> Result<State> {
+    let p_search = matches.value_of("PODCAST").unwrap();
+    let re_pod = Regex::new(&format!("(?i){}", &p_search))?;
+    let sub = match state
+        .subscriptions
+        .iter_mut()
+        .find(|sub| re_pod.is_match(sub.title()))
+    {
+        Some(sub) => sub,
+        None => {
+            eprintln!("No subscription matches {}", p_search);
+            return Ok(state);
+        }
+    };
+
+    match matches.value_of("KEY") {
+        Some(key) if matches.occurrences_of("UNSET") > 0 => sub.config.set(key, None)?,
+        Some(key) => match matches.value_of("VALUE") {
+            Some(value) => sub.config.set(key, Some(value))?,
+            None => println!(
+                "{}",
+                sub.config.get(key)?.unwrap_or_else(|| "(global)".into())
+            ),
+        },
+        None => list_subscription_config(sub)?,
+    }
+    Ok(state)
+}
+
+pub fn tag(mut state: State, matches: &ArgMatches) -> Result<State> {
+    let p_search = matches.value_of("PODCAST").unwrap();
+    let re_pod = Regex::new(&format!("(?i){}", &p_search))?;
+    let sub = match state
+        .subscriptions
+        .iter_mut()
+        .find(|sub| re_pod.is_match(sub.title()))
+    {
+        Some(sub) => sub,
+        None => {
+            eprintln!("No subscription matches {}", p_search);
+            return Ok(state);
+        }
+    };
+
+    match matches.values_of("TAGS") {
+        Some(tags) if matches.occurrences_of("REMOVE") > 0 => {
+            for tag in tags {
+                sub.tags.remove(tag.trim());
+            }
+        }
+        Some(tags) => {
+            sub.tags.extend(
+                tags.map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                    .map(String::from),
+            );
+        }
+        None => {
+            let tags: Vec<&str> = sub.tags.iter().map(String::as_str).collect();
+            println!("{}: {}", sub.title(), tags.join(", "));
+        }
+    }
+    Ok(state)
+}
+
+pub fn rename(mut state: State, matches: &ArgMatches) -> Result<State> {
+    let p_search = matches.value_of("PODCAST").unwrap();
+    let title = matches.value_of("NAME").unwrap().trim();
+    let re_pod = Regex::new(&format!("(?i){}", &p_search))?;
+    if state.subscriptions.iter().any(|sub| sub.title() == title) {
+        eprintln!("There is already a subscription named {}", title);
+        return Ok(state);
+    }
+    match state
+        .subscriptions
+        .iter_mut()
+        .find(|sub| re_pod.is_match(sub.title()))
+    {
+        Some(sub) => {
+            let old_title = sub.title().to_owned();
+            sub.rename(title)?;
+            println!("Renamed {} to {}", old_title, sub.title());
+        }
+        None => eprintln!("No subscription matches {}", p_search),
+    }
+    Ok(state)
+}
+
+pub fn set_status(
+    mut state: State,
+    matches: &ArgMatches,
+    status: SubscriptionStatus,
+) -> Result<State> {
+    let p_search = matches.value_of("PODCAST").unwrap();
+    let re_pod = Regex::new(&format!("(?i){}", &p_search))?;
+    match state
+        .subscriptions
+        .iter_mut()
+        .find(|sub| re_pod.is_match(sub.title()))
+    {
+        Some(sub) => {
+            sub.status = status;
+            let verb = match status {
+                SubscriptionStatus::Active => "Resumed",
+                SubscriptionStatus::Paused => "Paused",
+                SubscriptionStatus::Archived => "Archived",
+            };
+            println!("{} {}", verb, sub.title());
+        }
+        None => eprintln!("No subscription matches {}", p_search),
+    }
+    Ok(state)
+}
+
 pub fn complete(state: State, matches: &ArgMatches) -> Result<State> {
     match matches.value_of("SHELL") {
         Some(shell) => print_completion(&state, shell),
@@ -153,7 +426,7 @@ pub fn complete(state: State, matches: &ArgMatches) -> Result<State> {
     Ok(state)
 }
 
-pub async fn search(state: State, matches: &ArgMatches) -> Result<State> {
+pub async fn search(state: State, matches: &ArgMatches) -> Result<(State, bool)> {
     let podcast = matches
         .values_of("PODCAST")
         .unwrap()
@@ -167,7 +440,7 @@ pub async fn search(state: State, matches: &ArgMatches) -> Result<State> {
     let resp = podcast_search::search(&podcast).await?;
     if resp.results.is_empty() {
         println!("No Results");
-        return Ok(state);
+        return Ok((state, true));
     }
 
     {
@@ -189,7 +462,7 @@ pub async fn search(state: State, matches: &ArgMatches) -> Result<State> {
     let mut input = String::new();
     io::stdin().read_line(&mut input)?;
     if input.to_lowercase().trim() != "y" {
-        return Ok(state);
+        return Ok((state, true));
     }
 
     print!("Which one? (#): ");
@@ -199,15 +472,15 @@ pub async fn search(state: State, matches: &ArgMatches) -> Result<State> {
     let n: usize = num_input.trim().parse()?;
     if n > resp.results.len() {
         eprintln!("Invalid!");
-        return Ok(state);
+        return Ok((state, true));
     }
 
     let rss_resp = &resp.results[n];
     match &rss_resp.feed_url {
-        Some(r) => sub(state, r).await,
+        Some(r) => sub(state, r, &[]).await,
         None => {
             eprintln!("Subscription failed. No url in API response.");
-            Ok(state)
+            Ok((state, true))
         }
     }
 }
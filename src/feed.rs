@@ -0,0 +1,1047 @@
+use anyhow::{anyhow, Result};
+use atom_syndication::{Entry, Feed, Link};
+use chrono::DateTime;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use rss::extension::itunes::{ITunesChannelExtension, ITunesItemExtension};
+use rss::extension::{atom, itunes};
+use rss::extension::{Extension, ExtensionMap};
+use rss::{Category, Channel, Enclosure, Guid, Item};
+
+use std::collections::BTreeMap;
+
+use crate::repair::{self, Repair};
+use crate::structs::{Episode, EpisodeType, Funding, Person, Podcast, Source};
+use crate::{transcript, utils};
+
+const JSON_FEED_VERSION: &str = "https://jsonfeed.org/version/1";
+
+const PODCAST_PREFIX: &str = "podcast";
+const PODCAST_NAMESPACE: &str = "https://podcastindex.org/namespace/1.0";
+/// The Podcasting 2.0 namespace, and what it was while it was a draft
+const PODCAST_NAMESPACES: [&str; 2] = [
+    PODCAST_NAMESPACE,
+    "https://github.com/Podcastindex-org/podcast-namespace/blob/main/docs/1.0.md",
+];
+
+/// A JSON Feed (https://www.jsonfeed.org/version/1.1/), keeping only what we use
+#[derive(Debug, Deserialize)]
+struct JsonFeed {
+    version: String,
+    title: String,
+    home_page_url: Option<String>,
+    description: Option<String>,
+    icon: Option<String>,
+    language: Option<String>,
+    #[serde(default)]
+    items: Vec<JsonItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonItem {
+    id: serde_json::Value,
+    title: Option<String>,
+    image: Option<String>,
+    date_published: Option<String>,
+    #[serde(default)]
+    attachments: Vec<JsonAttachment>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonAttachment {
+    url: String,
+    mime_type: String,
+    size_in_bytes: Option<u64>,
+    duration_in_seconds: Option<f64>,
+}
+
+/// Parses an RSS, Atom or JSON feed, telling them apart by the root element
+pub fn parse(content: &[u8]) -> Result<Podcast> {
+    let start = content.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(content);
+    if start.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{') {
+        return json_to_podcast(serde_json::from_slice(start)?);
+    }
+    match root_element(content)?.as_str() {
+        "feed" => Ok(atom_to_podcast(&Feed::read_from(content)?)),
+        _ => {
+            let mut channel = Channel::read_from(content)?;
+            add_alternate_sources(&mut channel, content)?;
+            Ok(rss_to_podcast(&channel))
+        }
+    }
+}
+
+/// Parses a feed like `parse`, and if that fails, repairs the common ways feeds are
+/// malformed and tries again. Returns what had to be repaired.
+pub fn parse_lenient(content: &[u8]) -> Result<(Podcast, Vec<Repair>)> {
+    let err = match parse(content) {
+        Ok(podcast) => return Ok((podcast, vec![])),
+        Err(err) => err,
+    };
+    let (repaired, repairs) = repair::repair(content);
+    if repairs.is_empty() {
+        return Err(err);
+    }
+    match parse(repaired.as_bytes()) {
+        Ok(podcast) => Ok((podcast, repairs)),
+        Err(_) => Err(err),
+    }
+}
+
+/// The rss crate drops the self-closing children of extension elements, which is how the
+/// `<podcast:source>` elements of a `<podcast:alternateEnclosure>` are usually written,
+/// so they're read separately and put back.
+fn add_alternate_sources(channel: &mut Channel, content: &[u8]) -> Result<()> {
+    let prefix = podcast_prefix(&channel.namespaces).to_string();
+    let has_alternates = channel.items.iter().any(|item| {
+        item.extensions
+            .get(&prefix)
+            .is_some_and(|tags| tags.contains_key("alternateEnclosure"))
+    });
+    if !has_alternates {
+        return Ok(());
+    }
+    let alternate_name = format!("{}:alternateEnclosure", prefix);
+    let source_name = format!("{}:source", prefix);
+
+    // The sources of each alternate enclosure of each item the rss crate reads, which are the
+    // items in the channel and then those next to it, as in RSS 1.0
+    let mut items: Vec<(bool, Vec<Vec<Extension>>)> = vec![];
+    // The names of the elements we're in, and how deep the item we're in is
+    let mut path: Vec<Vec<u8>> = vec![];
+    let mut current = None;
+    let mut in_alternate = false;
+    let mut reader = Reader::from_reader(content);
+    let mut buf = vec![];
+    loop {
+        let event = reader.read_event(&mut buf)?;
+        let item = current
+            .and(items.last_mut())
+            .map(|(_, alternates)| alternates);
+        match &event {
+            Event::Start(e) if current.is_none() && e.name() == b"item" => {
+                let in_channel = match path.as_slice() {
+                    [_, parent] if parent.as_slice() == b"channel" => Some(true),
+                    [_] => Some(false),
+                    _ => None,
+                };
+                if let Some(in_channel) = in_channel {
+                    items.push((in_channel, vec![]));
+                    current = Some(path.len());
+                }
+            }
+            Event::Start(e) | Event::Empty(e) if e.name() == alternate_name.as_bytes() => {
+                if let Some(item) = item {
+                    item.push(vec![]);
+                }
+                in_alternate = matches!(event, Event::Start(_));
+            }
+            Event::End(e) if e.name() == alternate_name.as_bytes() => in_alternate = false,
+            Event::Start(e) | Event::Empty(e)
+                if in_alternate && e.name() == source_name.as_bytes() =>
+            {
+                let mut attrs = BTreeMap::new();
+                for attr in e.attributes().with_checks(false).flatten() {
+                    attrs.insert(
+                        String::from_utf8_lossy(attr.key).into_owned(),
+                        attr.unescape_and_decode_value(&reader)?,
+                    );
+                }
+                if let Some(sources) = item.and_then(|item| item.last_mut()) {
+                    sources.push(Extension {
+                        name: source_name.clone(),
+                        attrs,
+                        ..Default::default()
+                    });
+                }
+            }
+            _ => {}
+        }
+        match &event {
+            Event::Start(e) => path.push(e.name().to_vec()),
+            Event::End(_) => {
+                path.pop();
+                if current == Some(path.len()) {
+                    current = None;
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    let (mut in_channel, others): (Vec<_>, Vec<_>) =
+        items.into_iter().partition(|(in_channel, _)| *in_channel);
+    in_channel.extend(others);
+    for (item, (_, alternates)) in channel.items.iter_mut().zip(in_channel) {
+        let tags = item
+            .extensions
+            .get_mut(&prefix)
+            .and_then(|tags| tags.get_mut("alternateEnclosure"));
+        for (tag, sources) in tags.into_iter().flatten().zip(alternates) {
+            tag.children.insert("source".into(), sources);
+        }
+    }
+    Ok(())
+}
+
+/// The local name of the first element in the document
+fn root_element(content: &[u8]) -> Result<String> {
+    let mut reader = Reader::from_reader(content);
+    let mut buf = vec![];
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                return Ok(String::from_utf8_lossy(e.local_name()).into_owned())
+            }
+            Event::Eof => return Err(anyhow!("The feed is empty")),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+fn rss_to_podcast(channel: &Channel) -> Podcast {
+    let mut podcast = Podcast {
+        title: channel.title().to_string(),
+        link: channel.link().to_string(),
+        description: non_empty(channel.description()),
+        author: channel.managing_editor().map(String::from),
+        language: channel.language().map(String::from),
+        categories: channel
+            .categories()
+            .iter()
+            .map(|category| category.name().to_string())
+            .collect(),
+        image: channel.image().map(|image| image.url().to_string()),
+        older_page: channel.atom_ext().and_then(|ext| older_page(ext.links())),
+        ..Default::default()
+    };
+    let prefix = podcast_prefix(channel.namespaces());
+    podcast.episodes = channel
+        .items()
+        .iter()
+        .map(|item| item_to_episode(item, prefix))
+        .collect();
+    add_channel_extensions(
+        &mut podcast,
+        channel.itunes_ext(),
+        channel.extensions(),
+        prefix,
+    );
+    podcast
+}
+
+fn item_to_episode(item: &Item, prefix: &str) -> Episode {
+    let mut episode = Episode {
+        id: episode_id(
+            item.guid().map(Guid::value),
+            item.enclosure().map(Enclosure::url),
+            item.title(),
+            item.pub_date(),
+        ),
+        title: item.title().map(String::from),
+        published: item
+            .pub_date()
+            .and_then(|date| DateTime::parse_from_rfc2822(date.trim()).ok()),
+        enclosure: item.enclosure().map(|enclosure| Source {
+            url: enclosure.url().trim().to_string(),
+            mime_type: non_empty(enclosure.mime_type()),
+            length: parse_length(enclosure.length()),
+            bitrate: None,
+        }),
+        ..Default::default()
+    };
+    add_item_extensions(&mut episode, item.itunes_ext(), item.extensions(), prefix);
+    episode
+}
+
+fn atom_to_podcast(feed: &Feed) -> Podcast {
+    let prefix = podcast_prefix(feed.namespaces());
+    let itunes_prefix = namespace_prefix(feed.namespaces(), &[itunes::NAMESPACE], "itunes");
+    let extensions = convert_extensions(feed.extensions());
+    let itunes = extensions
+        .get(itunes_prefix)
+        .cloned()
+        .map(ITunesChannelExtension::from_map);
+    let mut podcast = Podcast {
+        title: feed.title().to_string(),
+        link: alternate_link(feed.links())
+            .unwrap_or_else(|| feed.id())
+            .to_string(),
+        description: feed.subtitle().and_then(|text| non_empty(text)),
+        language: feed.lang().map(String::from),
+        categories: feed
+            .categories()
+            .iter()
+            .map(|category| category.term().to_string())
+            .collect(),
+        image: feed.logo().or_else(|| feed.icon()).map(String::from),
+        older_page: older_page(feed.links()),
+        episodes: feed
+            .entries()
+            .iter()
+            .map(|entry| entry_to_episode(entry, prefix, itunes_prefix))
+            .collect(),
+        ..Default::default()
+    };
+    add_channel_extensions(&mut podcast, itunes.as_ref(), &extensions, prefix);
+    podcast
+}
+
+fn entry_to_episode(entry: &Entry, prefix: &str, itunes_prefix: &str) -> Episode {
+    let extensions = convert_extensions(entry.extensions());
+    let itunes = extensions
+        .get(itunes_prefix)
+        .cloned()
+        .map(ITunesItemExtension::from_map);
+    let mut enclosures = entry
+        .links()
+        .iter()
+        .filter(|link| link.rel() == "enclosure");
+    let enclosure = enclosures.next();
+    let published = entry.published().unwrap_or_else(|| entry.updated());
+
+    let mut episode = Episode {
+        id: episode_id(
+            Some(entry.id()),
+            enclosure.map(Link::href),
+            Some(entry.title()),
+            Some(&published.to_rfc2822()),
+        ),
+        title: Some(entry.title().to_string()),
+        published: Some(*published),
+        enclosure: enclosure.map(link_source),
+        ..Default::default()
+    };
+    add_item_extensions(&mut episode, itunes.as_ref(), &extensions, prefix);
+    episode.alternates.extend(enclosures.map(link_source));
+    episode
+}
+
+/// The media file an Atom `enclosure` link points to
+fn link_source(link: &Link) -> Source {
+    Source {
+        url: link.href().trim().to_string(),
+        mime_type: link.mime_type().and_then(non_empty),
+        length: link.length().and_then(parse_length),
+        bitrate: None,
+    }
+}
+
+fn json_to_podcast(feed: JsonFeed) -> Result<Podcast> {
+    if !feed.version.starts_with(JSON_FEED_VERSION) {
+        return Err(anyhow!("Unsupported JSON Feed version {}", feed.version));
+    }
+    Ok(Podcast {
+        title: feed.title,
+        link: feed.home_page_url.unwrap_or_default(),
+        description: feed.description.as_deref().and_then(non_empty),
+        language: feed.language,
+        image: feed.icon,
+        episodes: feed.items.into_iter().map(json_to_episode).collect(),
+        ..Default::default()
+    })
+}
+
+fn json_to_episode(item: JsonItem) -> Episode {
+    let id = match item.id {
+        serde_json::Value::String(id) => id,
+        id => id.to_string(),
+    };
+    let published = item
+        .date_published
+        .and_then(|date| DateTime::parse_from_rfc3339(&date).ok());
+    let id = episode_id(
+        Some(&id),
+        item.attachments
+            .first()
+            .map(|attachment| attachment.url.as_str()),
+        item.title.as_deref(),
+        published.map(|date| date.to_rfc2822()).as_deref(),
+    );
+    let duration = item
+        .attachments
+        .first()
+        .and_then(|attachment| attachment.duration_in_seconds)
+        .and_then(|secs| utils::duration_from_secs(secs.round()));
+    // Attachments are alternative formats of the same episode, so the first one is the
+    // enclosure and the rest are alternates
+    let mut sources = item.attachments.into_iter().map(|attachment| Source {
+        url: attachment.url.trim().to_string(),
+        mime_type: non_empty(&attachment.mime_type),
+        length: attachment.size_in_bytes.filter(|size| *size > 0),
+        bitrate: None,
+    });
+
+    Episode {
+        id,
+        title: item.title,
+        published,
+        enclosure: sources.next(),
+        alternates: sources.collect(),
+        duration,
+        image: item.image,
+        ..Default::default()
+    }
+}
+
+/// A stable identity for an episode: its guid, else its enclosure URL, else its title and
+/// publish date. These are saved as the episodes we've seen, so they must never change.
+fn episode_id(
+    guid: Option<&str>,
+    enclosure_url: Option<&str>,
+    title: Option<&str>,
+    pub_date: Option<&str>,
+) -> String {
+    if let Some(guid) = guid.filter(|guid| !guid.is_empty()) {
+        return guid.to_string();
+    }
+    if let Some(url) = enclosure_url.filter(|url| !url.is_empty()) {
+        return url.to_string();
+    }
+    format!(
+        "{}|{}",
+        title.unwrap_or_default(),
+        pub_date.unwrap_or_default()
+    )
+}
+
+/// Drops repeated categories, ignoring case, and ones that a more specific category such as
+/// "Technology > Podcasting" already covers
+fn dedup_categories(categories: Vec<String>) -> Vec<String> {
+    let mut seen: Vec<String> = vec![];
+    for category in categories {
+        let category = category.trim();
+        if !category.is_empty() && !seen.iter().any(|c| c.eq_ignore_ascii_case(category)) {
+            seen.push(category.to_string());
+        }
+    }
+    let covered = |category: &String| {
+        let parent = format!("{} > ", category.to_lowercase());
+        seen.iter()
+            .any(|other| other.to_lowercase().starts_with(&parent))
+    };
+    seen.iter().filter(|c| !covered(c)).cloned().collect()
+}
+
+/// Fills in what the iTunes and Podcasting 2.0 tags of a feed say about the podcast.
+/// The iTunes tags are preferred to the feed's own.
+fn add_channel_extensions(
+    podcast: &mut Podcast,
+    itunes: Option<&ITunesChannelExtension>,
+    extensions: &ExtensionMap,
+    prefix: &str,
+) {
+    let mut categories = vec![];
+    if let Some(itunes) = itunes {
+        podcast.description = podcast
+            .description
+            .take()
+            .or_else(|| itunes.summary().and_then(non_empty));
+        podcast.author = itunes.author().map(String::from).or(podcast.author.take());
+        podcast.image = itunes.image().map(String::from).or(podcast.image.take());
+        podcast.explicit = itunes.explicit().and_then(parse_explicit);
+        podcast.new_feed_url = itunes
+            .new_feed_url()
+            .filter(|url| !url.trim().is_empty())
+            .map(String::from);
+        for category in itunes.categories() {
+            let mut name = category.text().to_string();
+            let mut subcategory = category.subcategory();
+            while let Some(sub) = subcategory {
+                name = format!("{} > {}", name, sub.text());
+                subcategory = sub.subcategory();
+            }
+            categories.push(name);
+        }
+    }
+    categories.append(&mut podcast.categories);
+    podcast.categories = dedup_categories(categories);
+
+    podcast.funding = podcast_tags(extensions, prefix, "funding")
+        .iter()
+        .filter_map(|tag| {
+            Some(Funding {
+                url: tag.attrs().get("url")?.clone(),
+                text: tag.value().map(str::trim).map(String::from),
+            })
+        })
+        .collect();
+    podcast.persons = podcast_tags(extensions, prefix, "person")
+        .iter()
+        .filter_map(|tag| {
+            Some(Person {
+                name: tag.value()?.trim().to_string(),
+                role: tag.attrs().get("role").cloned(),
+                href: tag.attrs().get("href").cloned(),
+            })
+        })
+        .collect();
+    podcast.locked = podcast_tags(extensions, prefix, "locked")
+        .first()
+        .and_then(|tag| {
+            let locked = match tag.value()?.trim().to_lowercase().as_str() {
+                "yes" => true,
+                "no" => false,
+                _ => return None,
+            };
+            Some((locked, tag.attrs().get("owner").cloned()))
+        });
+    podcast.guid = podcast_tags(extensions, prefix, "guid")
+        .iter()
+        .filter_map(Extension::value)
+        .find_map(non_empty);
+}
+
+/// Fills in what the iTunes and Podcasting 2.0 tags of an item say about the episode
+fn add_item_extensions(
+    episode: &mut Episode,
+    itunes: Option<&ITunesItemExtension>,
+    extensions: &ExtensionMap,
+    prefix: &str,
+) {
+    if let Some(itunes) = itunes {
+        episode.duration = itunes.duration().and_then(utils::parse_duration);
+        episode.season = itunes
+            .season()
+            .and_then(|season| season.trim().parse().ok());
+        episode.number = itunes
+            .episode()
+            .and_then(|number| number.trim().parse().ok());
+        episode.episode_type = itunes.episode_type().and_then(parse_episode_type);
+        episode.explicit = itunes.explicit().and_then(parse_explicit);
+        episode.image = itunes.image().map(String::from);
+    }
+
+    // Each alternate enclosure lists one or more URLs it can be fetched from
+    episode.alternates = podcast_tags(extensions, prefix, "alternateEnclosure")
+        .iter()
+        .flat_map(|tag| {
+            let attrs = tag.attrs();
+            tag.children()
+                .get("source")
+                .into_iter()
+                .flatten()
+                .filter_map(move |source| {
+                    Some(Source {
+                        url: source.attrs().get("uri")?.trim().to_string(),
+                        mime_type: attrs.get("type").and_then(|t| non_empty(t)),
+                        length: attrs.get("length").and_then(|len| len.trim().parse().ok()),
+                        bitrate: attrs
+                            .get("bitrate")
+                            .and_then(|bitrate| bitrate.trim().parse::<f64>().ok())
+                            .map(|bitrate| bitrate.round() as u64),
+                    })
+                })
+        })
+        .collect();
+    episode.chapters_url = podcast_tags(extensions, prefix, "chapters")
+        .iter()
+        .filter_map(|tag| tag.attrs().get("url"))
+        .find_map(|url| non_empty(url));
+    episode.transcripts = podcast_tags(extensions, prefix, "transcript")
+        .iter()
+        .filter_map(|tag| {
+            Some(transcript::Link {
+                url: non_empty(tag.attrs().get("url")?)?,
+                format: transcript::Format::from_mime_type(tag.attrs().get("type")?)?,
+            })
+        })
+        .collect();
+}
+
+/// The prefix a feed declares for one of the given namespaces, or else the usual one, since
+/// some feeds use tags without declaring their namespace
+fn namespace_prefix<'a>(
+    namespaces: &'a BTreeMap<String, String>,
+    uris: &[&str],
+    usual: &'a str,
+) -> &'a str {
+    namespaces
+        .iter()
+        .find(|(_, uri)| uris.contains(&uri.trim()))
+        .map_or(usual, |(prefix, _)| prefix.as_str())
+}
+
+fn podcast_prefix(namespaces: &BTreeMap<String, String>) -> &str {
+    namespace_prefix(namespaces, &PODCAST_NAMESPACES, PODCAST_PREFIX)
+}
+
+/// Tags in the Podcasting 2.0 namespace with the given name, under the feed's prefix for it
+fn podcast_tags<'a>(extensions: &'a ExtensionMap, prefix: &str, name: &str) -> &'a [Extension] {
+    extensions
+        .get(prefix)
+        .and_then(|tags| tags.get(name))
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+/// The podcast as an RSS channel, which is how feeds are cached. Everything `parse` reads
+/// from a feed is written, so that the cache reads back as the same podcast.
+pub fn to_rss(podcast: &Podcast) -> Channel {
+    let mut extensions = ExtensionMap::new();
+    add_podcast_tags(
+        &mut extensions,
+        "guid",
+        podcast
+            .guid
+            .iter()
+            .map(|guid| podcast_tag("guid", Some(guid), &[]))
+            .collect(),
+    );
+    add_podcast_tags(
+        &mut extensions,
+        "funding",
+        podcast
+            .funding
+            .iter()
+            .map(|funding| {
+                podcast_tag(
+                    "funding",
+                    funding.text.as_deref(),
+                    &[("url", Some(&funding.url))],
+                )
+            })
+            .collect(),
+    );
+    add_podcast_tags(
+        &mut extensions,
+        "person",
+        podcast
+            .persons
+            .iter()
+            .map(|person| {
+                podcast_tag(
+                    "person",
+                    Some(&person.name),
+                    &[
+                        ("role", person.role.as_deref()),
+                        ("href", person.href.as_deref()),
+                    ],
+                )
+            })
+            .collect(),
+    );
+    add_podcast_tags(
+        &mut extensions,
+        "locked",
+        podcast
+            .locked
+            .iter()
+            .map(|(locked, owner)| {
+                podcast_tag(
+                    "locked",
+                    Some(if *locked { "yes" } else { "no" }),
+                    &[("owner", owner.as_deref())],
+                )
+            })
+            .collect(),
+    );
+    // The rss crate writes its Atom links without their prefix, so this one is written by hand
+    if let Some(href) = &podcast.older_page {
+        let link = Extension {
+            name: "atom:link".into(),
+            attrs: BTreeMap::from([
+                ("rel".to_string(), "next".to_string()),
+                ("href".to_string(), href.clone()),
+            ]),
+            ..Default::default()
+        };
+        extensions.insert(
+            "atom".into(),
+            BTreeMap::from([("link".to_string(), vec![link])]),
+        );
+    }
+    let itunes_ext = ITunesChannelExtension {
+        author: podcast.author.clone(),
+        image: podcast.image.clone(),
+        explicit: podcast.explicit.map(|explicit| explicit.to_string()),
+        new_feed_url: podcast.new_feed_url.clone(),
+        ..Default::default()
+    };
+
+    Channel {
+        title: podcast.title.clone(),
+        link: podcast.link.clone(),
+        description: podcast.description.clone().unwrap_or_default(),
+        language: podcast.language.clone(),
+        categories: podcast
+            .categories
+            .iter()
+            .map(|name| Category {
+                name: name.clone(),
+                domain: None,
+            })
+            .collect(),
+        items: podcast.episodes.iter().map(episode_to_item).collect(),
+        itunes_ext: Some(itunes_ext).filter(|ext| *ext != ITunesChannelExtension::default()),
+        extensions,
+        namespaces: BTreeMap::from([
+            (PODCAST_PREFIX.to_string(), PODCAST_NAMESPACE.to_string()),
+            ("atom".to_string(), atom::NAMESPACE.to_string()),
+        ]),
+        ..Default::default()
+    }
+}
+
+fn episode_to_item(episode: &Episode) -> Item {
+    let mut extensions = ExtensionMap::new();
+    add_podcast_tags(
+        &mut extensions,
+        "alternateEnclosure",
+        episode.alternates.iter().map(alternate_enclosure).collect(),
+    );
+    add_podcast_tags(
+        &mut extensions,
+        "chapters",
+        episode
+            .chapters_url
+            .iter()
+            .map(|url| {
+                podcast_tag(
+                    "chapters",
+                    None,
+                    &[
+                        ("url", Some(url)),
+                        ("type", Some("application/json+chapters")),
+                    ],
+                )
+            })
+            .collect(),
+    );
+    add_podcast_tags(
+        &mut extensions,
+        "transcript",
+        episode
+            .transcripts
+            .iter()
+            .map(|link| {
+                podcast_tag(
+                    "transcript",
+                    None,
+                    &[
+                        ("url", Some(&link.url)),
+                        ("type", Some(link.format.mime_type())),
+                    ],
+                )
+            })
+            .collect(),
+    );
+    let itunes_ext = ITunesItemExtension {
+        duration: episode
+            .duration
+            .map(|duration| duration.as_secs().to_string()),
+        season: episode.season.map(|season| season.to_string()),
+        episode: episode.number.map(|number| number.to_string()),
+        episode_type: episode.episode_type.map(|kind| kind.to_string()),
+        explicit: episode.explicit.map(|explicit| explicit.to_string()),
+        image: episode.image.clone(),
+        ..Default::default()
+    };
+
+    Item {
+        title: episode.title.clone(),
+        enclosure: episode.enclosure.as_ref().map(|source| Enclosure {
+            url: source.url.clone(),
+            length: source.length.unwrap_or_default().to_string(),
+            mime_type: source.mime_type.clone().unwrap_or_default(),
+        }),
+        guid: Some(Guid {
+            value: episode.id.clone(),
+            permalink: false,
+        }),
+        pub_date: episode.published.map(|date| date.to_rfc2822()),
+        itunes_ext: Some(itunes_ext).filter(|ext| *ext != ITunesItemExtension::default()),
+        extensions,
+        ..Default::default()
+    }
+}
+
+/// A `<podcast:alternateEnclosure>` with the one URL the source can be fetched from
+fn alternate_enclosure(source: &Source) -> Extension {
+    let length = source.length.map(|length| length.to_string());
+    let bitrate = source.bitrate.map(|bitrate| bitrate.to_string());
+    let mut tag = podcast_tag(
+        "alternateEnclosure",
+        None,
+        &[
+            ("type", source.mime_type.as_deref()),
+            ("length", length.as_deref()),
+            ("bitrate", bitrate.as_deref()),
+        ],
+    );
+    tag.children.insert(
+        "source".into(),
+        vec![podcast_tag("source", None, &[("uri", Some(&source.url))])],
+    );
+    tag
+}
+
+/// A tag in the Podcasting 2.0 namespace, with the attributes that have a value
+fn podcast_tag(name: &str, value: Option<&str>, attrs: &[(&str, Option<&str>)]) -> Extension {
+    Extension {
+        name: format!("{}:{}", PODCAST_PREFIX, name),
+        value: value.map(String::from),
+        attrs: attrs
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), (*value)?.to_string())))
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn add_podcast_tags(extensions: &mut ExtensionMap, name: &str, tags: Vec<Extension>) {
+    if !tags.is_empty() {
+        extensions
+            .entry(PODCAST_PREFIX.into())
+            .or_default()
+            .insert(name.into(), tags);
+    }
+}
+
+/// The link to the page with older episodes of a paged or archived feed (RFC 5005)
+fn older_page(links: &[Link]) -> Option<String> {
+    ["next", "prev-archive"]
+        .iter()
+        .find_map(|rel| links.iter().find(|link| link.rel() == *rel))
+        .map(|link| link.href().to_string())
+}
+
+fn non_empty(text: &str) -> Option<String> {
+    Some(text.trim())
+        .filter(|text| !text.is_empty())
+        .map(String::from)
+}
+
+/// A feed's length in bytes, where 0 is often used for "unknown"
+fn parse_length(length: &str) -> Option<u64> {
+    length.trim().parse().ok().filter(|length| *length > 0)
+}
+
+fn parse_explicit(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "yes" | "explicit" | "true" => Some(true),
+        "no" | "clean" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_episode_type(value: &str) -> Option<EpisodeType> {
+    match value.trim().to_lowercase().as_str() {
+        "full" => Some(EpisodeType::Full),
+        "trailer" => Some(EpisodeType::Trailer),
+        "bonus" => Some(EpisodeType::Bonus),
+        _ => None,
+    }
+}
+
+/// The link to the HTML version, which is the default relation in Atom
+fn alternate_link(links: &[Link]) -> Option<&str> {
+    links
+        .iter()
+        .find(|link| link.rel() == "alternate")
+        .map(Link::href)
+}
+
+fn convert_extensions(extensions: &atom_syndication::extension::ExtensionMap) -> ExtensionMap {
+    extensions
+        .iter()
+        .map(|(prefix, tags)| (prefix.clone(), convert_tags(tags)))
+        .collect()
+}
+
+fn convert_tags(
+    tags: &BTreeMap<String, Vec<atom_syndication::extension::Extension>>,
+) -> BTreeMap<String, Vec<Extension>> {
+    tags.iter()
+        .map(|(name, tags)| {
+            let tags = tags
+                .iter()
+                .map(|tag| Extension {
+                    name: tag.name.clone(),
+                    value: tag.value.clone(),
+                    attrs: tag.attrs.clone(),
+                    children: convert_tags(&tag.children),
+                })
+                .collect();
+            (name.clone(), tags)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_atom() {
+        let feed = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom" xmlns:podcast="https://podcastindex.org/namespace/1.0">
+  <title>Atom Show</title>
+  <id>urn:uuid:60a76c80-d399-11d9-b93C-0003939e0af6</id>
+  <updated>2022-10-02T10:00:00Z</updated>
+  <link href="https://example.com/"/>
+  <podcast:guid>917393e3-1b1e-5cef-ace4-edaa54e1f810</podcast:guid>
+  <entry>
+    <title>Episode 1</title>
+    <id>urn:uuid:1225c695-cfb8-4ebb-aaaa-80da344efa6a</id>
+    <updated>2022-10-01T10:00:00Z</updated>
+    <author><name>Host</name></author>
+    <link rel="enclosure" type="audio/mpeg" length="1234" href="https://example.com/1.mp3"/>
+  </entry>
+</feed>"#;
+        let podcast = parse(feed.as_bytes()).unwrap();
+        assert_eq!(podcast.title, "Atom Show");
+        assert_eq!(podcast.link, "https://example.com/");
+        assert_eq!(
+            podcast.guid.as_deref(),
+            Some("917393e3-1b1e-5cef-ace4-edaa54e1f810")
+        );
+
+        let episode = &podcast.episodes[0];
+        assert_eq!(episode.title.as_deref(), Some("Episode 1"));
+        assert_eq!(episode.id, "urn:uuid:1225c695-cfb8-4ebb-aaaa-80da344efa6a");
+        assert_eq!(
+            episode.published,
+            DateTime::parse_from_rfc3339("2022-10-01T10:00:00Z").ok()
+        );
+        assert_eq!(
+            episode.enclosure,
+            Some(Source {
+                url: "https://example.com/1.mp3".into(),
+                mime_type: Some("audio/mpeg".into()),
+                length: Some(1234),
+                bitrate: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_json_feed() {
+        let feed = r#"
+{
+    "version": "https://jsonfeed.org/version/1.1",
+    "title": "JSON Show",
+    "home_page_url": "https://example.com/",
+    "items": [
+        {
+            "id": "2",
+            "title": "Episode 2",
+            "date_published": "2022-10-02T10:00:00+02:00",
+            "authors": [{ "name": "Host" }],
+            "attachments": [
+                {
+                    "url": "https://example.com/2.mp3",
+                    "mime_type": "audio/mpeg",
+                    "size_in_bytes": 1234,
+                    "duration_in_seconds": 3600
+                },
+                { "url": "https://example.com/2.opus", "mime_type": "audio/opus" }
+            ]
+        },
+        { "id": 1, "content_text": "No audio" }
+    ]
+}"#;
+        let podcast = parse(feed.as_bytes()).unwrap();
+        assert_eq!(podcast.title, "JSON Show");
+        assert_eq!(podcast.link, "https://example.com/");
+
+        let episode = &podcast.episodes[0];
+        assert_eq!(episode.title.as_deref(), Some("Episode 2"));
+        assert_eq!(
+            episode.published.map(|date| date.to_rfc2822()).as_deref(),
+            Some("Sun, 02 Oct 2022 10:00:00 +0200")
+        );
+        assert_eq!(episode.duration, Some(Duration::from_secs(3600)));
+        let sources = episode.sources();
+        assert_eq!(sources[0].url, "https://example.com/2.mp3");
+        assert_eq!(sources[0].length, Some(1234));
+        assert_eq!(sources[0].mime_type.as_deref(), Some("audio/mpeg"));
+        assert_eq!(sources[1].url, "https://example.com/2.opus");
+        assert_eq!(sources[1].mime_type.as_deref(), Some("audio/opus"));
+
+        let episode = &podcast.episodes[1];
+        assert_eq!(episode.id, "1");
+        assert!(episode.enclosure.is_none());
+
+        let feed = r#"{"version": "https://example.com/version/2", "title": "Nope"}"#;
+        assert!(parse(feed.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rss() {
+        let feed = r#"<?xml version="1.0"?><rss version="2.0"><channel><title>RSS Show</title></channel></rss>"#;
+        assert_eq!(parse(feed.as_bytes()).unwrap().title, "RSS Show");
+    }
+
+    #[test]
+    fn test_podcast_namespace_prefix() {
+        let feed = r#"<rss version="2.0" xmlns:pc="https://podcastindex.org/namespace/1.0" xmlns:x="https://example.com/ns">
+<channel><title>Test</title>
+<pc:guid>917393e3-1b1e-5cef-ace4-edaa54e1f810</pc:guid>
+<x:related><item><pc:alternateEnclosure type="audio/ogg"><pc:source uri="https://example.com/x.ogg"/></pc:alternateEnclosure></item></x:related>
+<item>
+  <title>Episode</title>
+  <podcast:alternateEnclosure type="audio/flac"><podcast:source uri="https://example.com/ep.flac"/></podcast:alternateEnclosure>
+  <pc:alternateEnclosure type="audio/opus"><pc:source uri="https://example.com/ep.opus"/></pc:alternateEnclosure>
+  <pc:transcript url="https://example.com/ep.vtt" type="text/vtt"/>
+</item>
+</channel></rss>"#;
+        let podcast = parse(feed.as_bytes()).unwrap();
+        assert_eq!(
+            podcast.guid.as_deref(),
+            Some("917393e3-1b1e-5cef-ace4-edaa54e1f810")
+        );
+        assert_eq!(podcast.episodes.len(), 1);
+        let episode = &podcast.episodes[0];
+        let urls: Vec<String> = episode
+            .alternates
+            .iter()
+            .map(|source| source.url.clone())
+            .collect();
+        assert_eq!(urls, vec!["https://example.com/ep.opus"]);
+        assert_eq!(episode.transcripts.len(), 1);
+    }
+
+    #[test]
+    fn test_cache_round_trip() {
+        let feed = r#"<rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd" xmlns:podcast="https://podcastindex.org/namespace/1.0" xmlns:atom="http://www.w3.org/2005/Atom">
+<channel><title>Test</title><link>https://example.com/</link>
+<itunes:summary>About</itunes:summary>
+<itunes:author>Host</itunes:author>
+<itunes:explicit>yes</itunes:explicit>
+<itunes:category text="Technology"><itunes:category text="Podcasting"/></itunes:category>
+<itunes:new-feed-url>https://example.com/new.xml</itunes:new-feed-url>
+<atom:link rel="next" href="page2.xml"/>
+<podcast:guid>917393e3-1b1e-5cef-ace4-edaa54e1f810</podcast:guid>
+<podcast:locked>no</podcast:locked>
+<podcast:funding url="https://example.com/donate">Support us</podcast:funding>
+<podcast:person role="host">Jane Doe</podcast:person>
+<item>
+  <title>Episode 2</title>
+  <pubDate>Sun, 02 Oct 2022 10:00:00 +0200</pubDate>
+  <enclosure url="https://example.com/2.mp3" length="1234" type="audio/mpeg"/>
+  <itunes:duration>1:00:00</itunes:duration>
+  <itunes:episode>2</itunes:episode>
+  <itunes:episodeType>bonus</itunes:episodeType>
+  <podcast:alternateEnclosure type="audio/opus" bitrate="64000">
+    <podcast:source uri="https://example.com/2.opus"/>
+  </podcast:alternateEnclosure>
+  <podcast:chapters url="https://example.com/2.json" type="application/json+chapters"/>
+  <podcast:transcript url="https://example.com/2.vtt" type="text/vtt"/>
+</item>
+<item><title>Episode 1</title><pubDate>Sat, 01 Oct 2022 10:00:00 +0200</pubDate></item>
+</channel></rss>"#;
+        let podcast = parse(feed.as_bytes()).unwrap();
+        let mut cache = vec![];
+        to_rss(&podcast).write_to(&mut cache).unwrap();
+        assert_eq!(parse(&cache).unwrap(), podcast);
+        assert_eq!(
+            podcast.episodes[1].id,
+            "Episode 1|Sat, 01 Oct 2022 10:00:00 +0200"
+        );
+    }
+}
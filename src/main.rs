@@ -5,14 +5,21 @@ extern crate lazy_static;
 extern crate serde;
 
 mod actions;
+mod auth;
 mod cargo_parser;
+mod chapters;
 mod command;
 mod download;
 mod executor;
+mod feed;
+mod opml;
 mod parser;
 mod playback;
+mod repair;
 mod structs;
+mod transcript;
 mod utils;
+mod verify;
 
 use self::structs::*;
 use anyhow::Result;
@@ -48,10 +55,14 @@ async fn main() -> Result<()> {
     let command = parse_command(state, matches);
 
     // After running the given command, we return a new state to persist
-    let new_state = run_command(command).await?;
+    let (new_state, all_downloaded) = run_command(command).await?;
 
     // Persist new state
     let public_state: PublicState = new_state.into();
     public_state.save()?;
+
+    if !all_downloaded {
+        std::process::exit(1);
+    }
     Ok(())
 }
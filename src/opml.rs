@@ -0,0 +1,155 @@
+use crate::structs::Subscription;
+use anyhow::Result;
+
+use std::io::{BufRead, Write};
+
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+
+/// A feed found in an OPML document
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outline {
+    pub title: Option<String>,
+    pub url: String,
+    pub tags: Vec<String>,
+}
+
+/// Collects every outline with an `xmlUrl`, including those nested in folders
+pub fn parse<R: BufRead>(reader: R) -> Result<Vec<Outline>> {
+    let mut reader = Reader::from_reader(reader);
+    reader.trim_text(true);
+
+    let mut outlines = vec![];
+    let mut buf = vec![];
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(ref e) | Event::Empty(ref e) if e.name() == b"outline" => {
+                let mut title = None;
+                let mut text = None;
+                let mut url = None;
+                let mut tags = vec![];
+                for attr in e.attributes() {
+                    let attr = attr?;
+                    let value = attr.unescape_and_decode_value(&reader)?;
+                    match attr.key {
+                        b"title" => title = Some(value),
+                        b"text" => text = Some(value),
+                        b"xmlUrl" => url = Some(value),
+                        // Categories are comma separated, and may be slash delimited paths
+                        b"category" => tags.extend(
+                            value
+                                .split(',')
+                                .map(|tag| tag.trim().trim_matches('/'))
+                                .filter(|tag| !tag.is_empty())
+                                .map(String::from),
+                        ),
+                        _ => {}
+                    }
+                }
+                if let Some(url) = url.filter(|url| !url.trim().is_empty()) {
+                    outlines.push(Outline {
+                        title: title.or(text),
+                        url: url.trim().to_string(),
+                        tags,
+                    });
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(outlines)
+}
+
+/// Writes the given subscriptions as an OPML 2.0 document
+pub fn write<W: Write>(subscriptions: &[Subscription], writer: W) -> Result<()> {
+    let mut writer = Writer::new_with_indent(writer, b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new(b"1.0", Some(b"UTF-8"), None)))?;
+    writer.write_event(Event::Start(
+        BytesStart::borrowed_name(b"opml").with_attributes(vec![("version", "2.0")]),
+    ))?;
+
+    writer.write_event(Event::Start(BytesStart::borrowed_name(b"head")))?;
+    writer.write_event(Event::Start(BytesStart::borrowed_name(b"title")))?;
+    writer.write_event(Event::Text(BytesText::from_plain_str(
+        "podcast subscriptions",
+    )))?;
+    writer.write_event(Event::End(BytesEnd::borrowed(b"title")))?;
+    writer.write_event(Event::End(BytesEnd::borrowed(b"head")))?;
+
+    writer.write_event(Event::Start(BytesStart::borrowed_name(b"body")))?;
+    for sub in subscriptions {
+        let mut outline = BytesStart::borrowed_name(b"outline").with_attributes(vec![
+            ("type", "rss"),
+            ("text", sub.title()),
+            ("title", sub.title()),
+            ("xmlUrl", sub.url.as_str()),
+        ]);
+        let categories: Vec<&str> = sub.tags.iter().map(String::as_str).collect();
+        if !categories.is_empty() {
+            outline.push_attribute(("category", categories.join(",").as_str()));
+        }
+        writer.write_event(Event::Empty(outline))?;
+    }
+    writer.write_event(Event::End(BytesEnd::borrowed(b"body")))?;
+    writer.write_event(Event::End(BytesEnd::borrowed(b"opml")))?;
+    writer.inner().write_all(b"\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_nested_outlines() {
+        let doc = r#"<?xml version="1.0"?>
+<opml version="2.0">
+  <head><title>Feeds</title></head>
+  <body>
+    <outline text="Tech">
+      <outline type="rss" text="Show &amp; Tell" category="/tech, news" xmlUrl="https://example.com/a.xml"/>
+    </outline>
+    <outline type="rss" title="Other" text="ignored" xmlUrl="https://example.com/b.xml"></outline>
+  </body>
+</opml>"#;
+        assert_eq!(
+            parse(doc.as_bytes()).unwrap(),
+            vec![
+                Outline {
+                    title: Some("Show & Tell".into()),
+                    url: "https://example.com/a.xml".into(),
+                    tags: vec!["tech".into(), "news".into()],
+                },
+                Outline {
+                    title: Some("Other".into()),
+                    url: "https://example.com/b.xml".into(),
+                    tags: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let subscriptions = vec![Subscription {
+            title: "Q&A <Live>".into(),
+            url: "https://example.com/feed?a=1&b=2".into(),
+            tags: vec!["kids".to_string(), "news".to_string()]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        }];
+        let mut out = vec![];
+        write(&subscriptions, &mut out).unwrap();
+        assert_eq!(
+            parse(&out[..]).unwrap(),
+            vec![Outline {
+                title: Some("Q&A <Live>".into()),
+                url: "https://example.com/feed?a=1&b=2".into(),
+                tags: vec!["kids".into(), "news".into()],
+            }]
+        );
+    }
+}
@@ -1,6 +1,7 @@
+use crate::structs::SubscriptionConfig;
 use clap::{Arg, Command};
 
-pub fn get_app(version: &str) -> Command {
+pub fn get_app(version: &str) -> Command<'_> {
     Command::new("podcast")
         .version(version)
         .author("Nathan J. <njaremko@gmail.com>")
@@ -18,9 +19,16 @@ pub fn get_app(version: &str) -> Command {
                 .arg(
                     Arg::new("PODCAST")
                         .help("Regex for subscribed podcast")
-                        .required(true)
+                        .required_unless_present("TAG")
                         .index(1),
                 )
+                .arg(
+                    Arg::new("TAG")
+                        .long("tag")
+                        .help("Download from every podcast with this tag")
+                        .takes_value(true)
+                        .required(false),
+                )
                 .arg(
                     Arg::new("EPISODE")
                         .required(false)
@@ -55,7 +63,7 @@ pub fn get_app(version: &str) -> Command {
                     Arg::new("TEMPLATE")
                         .short('t')
                         .long("template")
-                        .help("Name the downloaded file according to the provided template. Supports {title} and {number}.")
+                        .help("Name the downloaded file according to the provided template. Supports {title} and {number}, which is the publisher's episode number where available.")
                         .takes_value(true)
                         .required(false),
                 )
@@ -65,21 +73,56 @@ pub fn get_app(version: &str) -> Command {
                         .long("all")
                         .help("Download all matching episodes")
                         .required(false),
-                ),
+                )
+                .arg(limit_rate()),
         )
         .subcommand(
-            Command::new("ls").about("list episodes of podcast").arg(
-                Arg::new("PODCAST")
-                    .help("Regex for subscribed podcast")
-                    .index(1),
-            ),
+            Command::new("ls")
+                .about("list episodes of podcast")
+                .arg(
+                    Arg::new("PODCAST")
+                        .help("Regex for subscribed podcast")
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("ALL")
+                        .short('a')
+                        .long("all")
+                        .help("Include archived podcasts")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("TAG")
+                        .long("tag")
+                        .help("Only list podcasts with this tag")
+                        .takes_value(true)
+                        .conflicts_with("PODCAST")
+                        .required(false),
+                ),
         )
         .subcommand(
-            Command::new("list").about("list episodes of podcast").arg(
-                Arg::new("PODCAST")
-                    .help("Regex for subscribed podcast")
-                    .index(1),
-            ),
+            Command::new("list")
+                .about("list episodes of podcast")
+                .arg(
+                    Arg::new("PODCAST")
+                        .help("Regex for subscribed podcast")
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("ALL")
+                        .short('a')
+                        .long("all")
+                        .help("Include archived podcasts")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("TAG")
+                        .long("tag")
+                        .help("Only list podcasts with this tag")
+                        .takes_value(true)
+                        .conflicts_with("PODCAST")
+                        .required(false),
+                ),
         )
         .subcommand(
             Command::new("play")
@@ -104,6 +147,58 @@ pub fn get_app(version: &str) -> Command {
                         .required(false),
                 ),
         )
+        .subcommand(
+            Command::new("info")
+                .about("show what the feed says about a podcast")
+                .arg(
+                    Arg::new("PODCAST")
+                        .help("Regex for subscribed podcast")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            Command::new("chapters")
+                .about("list the chapters of an episode")
+                .arg(
+                    Arg::new("PODCAST")
+                        .help("Regex for subscribed podcast")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("EPISODE")
+                        .help("Episode index, or a part of its title")
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            Command::new("transcript")
+                .about("print the transcript of an episode")
+                .arg(
+                    Arg::new("PODCAST")
+                        .help("Regex for subscribed podcast")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("EPISODE")
+                        .help("Episode index, or a part of its title")
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            Command::new("grep")
+                .about("search the transcripts of downloaded episodes")
+                .arg(
+                    Arg::new("TEXT")
+                        .help("Regex to search for")
+                        .required(true)
+                        .index(1),
+                ),
+        )
         .subcommand(
             Command::new("search")
                 .about("searches for podcasts")
@@ -128,7 +223,16 @@ pub fn get_app(version: &str) -> Command {
                         .help("URL to RSS feed")
                         .required(true)
                         .index(1),
-                ),
+                )
+                .arg(
+                    Arg::new("AUTH")
+                        .long("auth")
+                        .help("Credentials for a private feed, see `podcast auth --help`")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .required(false),
+                )
+                .arg(limit_rate()),
         )
         .subcommand(
             Command::new("sub")
@@ -138,9 +242,187 @@ pub fn get_app(version: &str) -> Command {
                         .help("URL to RSS feed")
                         .required(true)
                         .index(1),
+                )
+                .arg(
+                    Arg::new("AUTH")
+                        .long("auth")
+                        .help("Credentials for a private feed, see `podcast auth --help`")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .required(false),
+                )
+                .arg(limit_rate()),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("subscribes to every podcast in an OPML file")
+                .arg(
+                    Arg::new("FILE")
+                        .help("OPML file to import")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("NO_DOWNLOAD")
+                        .long("no-download")
+                        .help("Don't download any episodes of the imported podcasts")
+                        .required(false),
+                )
+                .arg(limit_rate()),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("exports subscriptions as an OPML file")
+                .arg(
+                    Arg::new("OUTPUT")
+                        .short('o')
+                        .long("output")
+                        .help("File to write to, defaults to stdout")
+                        .takes_value(true)
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("config")
+                .about("show or change settings for a single subscription")
+                .arg(
+                    Arg::new("PODCAST")
+                        .help("Regex for subscribed podcast")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("KEY")
+                        .help("Setting to show or change")
+                        .possible_values(SubscriptionConfig::KEYS)
+                        .index(2),
+                )
+                .arg(Arg::new("VALUE").help("New value for the setting").index(3))
+                .arg(
+                    Arg::new("UNSET")
+                        .long("unset")
+                        .help("Go back to using the global value for the setting")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("tag")
+                .about("add or remove tags on a subscription")
+                .arg(
+                    Arg::new("PODCAST")
+                        .help("Regex for subscribed podcast")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("TAGS")
+                        .help("Tags to add, lists the podcast's tags if none are given")
+                        .index(2)
+                        .multiple_occurrences(true),
+                )
+                .arg(
+                    Arg::new("REMOVE")
+                        .short('r')
+                        .long("remove")
+                        .help("Remove the given tags instead of adding them")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("auth")
+                .about("set credentials for a private feed")
+                .long_about(
+                    "Set credentials that are sent with every request for a podcast's feed and episodes.\n\
+                     Secrets are never saved, only where to find them: env:VARIABLE reads an \
+                     environment variable, and cmd:COMMAND uses the first line printed by a command.\n\n\
+                     Examples:\n    \
+                     podcast auth show basic:me:env:SHOW_PASSWORD\n    \
+                     podcast auth show 'bearer:cmd:pass show podcast/show'\n    \
+                     podcast auth show header:X-Api-Key:env:SHOW_KEY",
+                )
+                .arg(
+                    Arg::new("PODCAST")
+                        .help("Regex for subscribed podcast")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("AUTH")
+                        .help("basic:USERNAME:SECRET, bearer:SECRET or header:NAME:SECRET, lists the current credentials if none are given")
+                        .index(2)
+                        .multiple_occurrences(true),
+                )
+                .arg(
+                    Arg::new("CLEAR")
+                        .long("clear")
+                        .help("Remove all credentials")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("rename")
+                .about("give a podcast a local name, moving its episodes along with it")
+                .arg(
+                    Arg::new("PODCAST")
+                        .help("Regex for subscribed podcast")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("NAME")
+                        .help("New name for the podcast")
+                        .required(true)
+                        .index(2),
                 ),
         )
-        .subcommand(Command::new("refresh").about("refresh subscribed podcasts"))
+        .subcommand(
+            Command::new("pause")
+                .about("stop checking a podcast for new episodes")
+                .arg(
+                    Arg::new("PODCAST")
+                        .help("Regex for subscribed podcast")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            Command::new("resume")
+                .about("resume checking a paused or archived podcast for new episodes")
+                .arg(
+                    Arg::new("PODCAST")
+                        .help("Regex for subscribed podcast")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            Command::new("archive")
+                .about("stop checking a podcast for new episodes and hide it from ls")
+                .arg(
+                    Arg::new("PODCAST")
+                        .help("Regex for subscribed podcast")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            Command::new("refresh")
+                .about("refresh subscribed podcasts")
+                .arg(
+                    Arg::new("TAG")
+                        .long("tag")
+                        .help("Only refresh podcasts with this tag")
+                        .takes_value(true)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("FULL")
+                        .long("full")
+                        .help("Fetch every page of feeds that split their episodes over several pages")
+                        .required(false),
+                )
+                .arg(limit_rate()),
+        )
         .subcommand(Command::new("update").about("check for updates"))
         .subcommand(
             Command::new("rm")
@@ -157,3 +439,30 @@ pub fn get_app(version: &str) -> Command {
                 ),
         )
 }
+
+/// `--limit-rate`, for the commands that download episodes
+fn limit_rate<'a>() -> Arg<'a> {
+    Arg::new("LIMIT_RATE")
+        .long("limit-rate")
+        .value_name("RATE")
+        .help("Limit the total download speed in bytes per second, such as 500K or 2M")
+        .takes_value(true)
+        .required(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ls_tag_excludes_podcast() {
+        for ls in &["ls", "list"] {
+            assert!(get_app("0")
+                .try_get_matches_from(vec!["podcast", ls, "--tag", "news"])
+                .is_ok());
+            assert!(get_app("0")
+                .try_get_matches_from(vec!["podcast", ls, "Show", "--tag", "news"])
+                .is_err());
+        }
+    }
+}
@@ -2,65 +2,109 @@ use crate::structs::*;
 use crate::utils::*;
 use anyhow::Result;
 
-use std::fs::File;
-use std::io::{self, BufReader, Read, Write};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
 use std::process::Command;
 
 use regex::Regex;
-use rss::Channel;
 use std::path::PathBuf;
 
-fn launch_player(url: &str) -> Result<()> {
-    if launch_mpv(url).is_err() {
-        if launch_vlc(url).is_err() {
-            return launch_sox(url);
-        }
+/// mpv's exit status when it couldn't play the file at all
+const MPV_FILE_NOT_PLAYED: i32 = 2;
+
+/// Plays a file or URL with the first player we find, returning whether playback started.
+/// Stopping playback partway, or the player exiting with an error afterwards, doesn't count
+/// as not starting, so the next source isn't tried for that.
+fn launch_player(url: &str, speed: Option<f64>) -> Result<bool> {
+    match launch_mpv(url, speed) {
+        Err(_) => match launch_vlc(url, speed) {
+            Err(_) => launch_sox(url, speed),
+            played => played,
+        },
+        played => played,
     }
-    Ok(())
 }
 
-fn launch_mpv(url: &str) -> Result<()> {
-    if let Err(err) = Command::new("mpv")
-        .args(&["--no-audio-display", "--ytdl=no", url])
-        .status()
-    {
-        let stderr = io::stderr();
-        let mut handle = stderr.lock();
-        match err.kind() {
-            io::ErrorKind::NotFound => {
-                writeln!(&mut handle, "Couldn't open mpv\nTrying vlc...").ok()
-            }
-            _ => writeln!(&mut handle, "Error: {}", err).ok(),
-        };
+/// Streams the first of the episode's sources that starts playing
+fn play_sources(sources: &[Source], speed: Option<f64>) -> Result<()> {
+    for (index, source) in sources.iter().enumerate() {
+        if launch_player(&source.url, speed)? {
+            break;
+        }
+        if index + 1 < sources.len() {
+            eprintln!("Couldn't play {}\nTrying the next source...", source.url);
+        }
     }
     Ok(())
 }
 
-fn launch_vlc(url: &str) -> Result<()> {
-    if let Err(err) = Command::new("vlc").args(&["-I ncurses", url]).status() {
-        let stderr = io::stderr();
-        let mut handle = stderr.lock();
-        match err.kind() {
-            io::ErrorKind::NotFound => {
-                writeln!(&mut handle, "Couldn't open vlc\nTrying sox...").ok()
-            }
-            _ => writeln!(&mut handle, "Error: {}", err).ok(),
-        };
+fn launch_mpv(url: &str, speed: Option<f64>) -> Result<bool> {
+    let mut command = Command::new("mpv");
+    command.args(["--no-audio-display", "--ytdl=no"]);
+    if let Some(speed) = speed {
+        command.arg(format!("--speed={}", speed));
+    }
+    match command.arg(url).status() {
+        Ok(status) => Ok(status.code() != Some(MPV_FILE_NOT_PLAYED)),
+        Err(err) => {
+            let stderr = io::stderr();
+            let mut handle = stderr.lock();
+            match err.kind() {
+                io::ErrorKind::NotFound => {
+                    writeln!(&mut handle, "Couldn't open mpv\nTrying vlc...").ok()
+                }
+                _ => writeln!(&mut handle, "Error: {}", err).ok(),
+            };
+            Err(err.into())
+        }
     }
-    Ok(())
 }
 
-fn launch_sox(url: &str) -> Result<()> {
-    if let Some(cleaned_url) = url.split('?').take(1).next() {
-        if let Err(err) = Command::new("play").args(&[cleaned_url]).status() {
+fn launch_vlc(url: &str, speed: Option<f64>) -> Result<bool> {
+    let mut command = Command::new("vlc");
+    command.args(["-I", "ncurses", "--play-and-exit"]);
+    if let Some(speed) = speed {
+        command.arg(format!("--rate={}", speed));
+    }
+    // vlc's exit status doesn't tell a file it couldn't play from one the user stopped
+    match command.arg(url).status() {
+        Ok(_) => Ok(true),
+        Err(err) => {
             let stderr = io::stderr();
             let mut handle = stderr.lock();
             match err.kind() {
                 io::ErrorKind::NotFound => {
-                    writeln!(&mut handle, "Couldn't open sox...aborting").ok()
+                    writeln!(&mut handle, "Couldn't open vlc\nTrying sox...").ok()
                 }
                 _ => writeln!(&mut handle, "Error: {}", err).ok(),
             };
+            Err(err.into())
+        }
+    }
+}
+
+fn launch_sox(url: &str, speed: Option<f64>) -> Result<bool> {
+    if let Some(cleaned_url) = url.split('?').take(1).next() {
+        let mut command = Command::new("play");
+        command.arg(cleaned_url);
+        if let Some(speed) = speed {
+            command.args(["tempo", &speed.to_string()]);
+        }
+        // Nor does sox's
+        match command.status() {
+            Ok(_) => return Ok(true),
+            Err(err) => {
+                let stderr = io::stderr();
+                let mut handle = stderr.lock();
+                match err.kind() {
+                    io::ErrorKind::NotFound => {
+                        writeln!(&mut handle, "Couldn't open sox...aborting").ok()
+                    }
+                    _ => writeln!(&mut handle, "Error: {}", err).ok(),
+                };
+                // Without a player, there's no point trying another source
+                return Err(err.into());
+            }
         }
     } else {
         let stderr = io::stderr();
@@ -68,6 +112,23 @@ fn launch_sox(url: &str) -> Result<()> {
         writeln!(&mut handle, "Given filename '{}' couldn't be parsed", url).ok();
     }
 
+    Ok(false)
+}
+
+/// Plays the episode from where it was downloaded, or else streams it
+fn play_episode(
+    state: &State,
+    subscription: &Subscription,
+    podcast: &Podcast,
+    episode: &Episode,
+) -> Result<()> {
+    let speed = subscription.config.playback_speed;
+    match subscription.saved_path(&state.config, podcast, episode)? {
+        Some(path) => {
+            launch_player(&path.to_string_lossy(), speed)?;
+        }
+        None => play_sources(&subscription.sources(&state.config, episode), speed)?,
+    }
     Ok(())
 }
 
@@ -80,21 +141,11 @@ pub fn play_latest(state: &State, p_search: &str) -> Result<()> {
             filename.push_str(".xml");
             path.push(filename);
 
-            let file: File = File::open(&path)?;
-            let podcast: Podcast = Podcast::from(Channel::read_from(BufReader::new(file))?);
+            let podcast: Podcast = Podcast::parse(&fs::read(&path)?)?;
             let episodes = podcast.episodes();
             let episode = episodes[0].clone();
 
-            filename = episode.title().unwrap();
-            filename.push_str(&episode.extension().unwrap());
-            path = get_podcast_dir()?;
-            path.push(podcast.title());
-            path.push(filename);
-            if path.exists() {
-                launch_player(path.to_str().unwrap())?;
-            } else {
-                launch_player(episode.url().unwrap())?;
-            }
+            play_episode(state, subscription, &podcast, &episode)?;
             return Ok(());
         }
     }
@@ -111,23 +162,11 @@ pub fn play_episode_by_num(state: &State, p_search: &str, ep_num_string: &str) -
                 filename.push_str(".xml");
                 path.push(filename);
 
-                let file: File = File::open(&path).unwrap();
-                let podcast = Podcast::from(Channel::read_from(BufReader::new(file)).unwrap());
+                let podcast = Podcast::parse(&fs::read(&path)?)?;
                 let episodes = podcast.episodes();
                 let episode = episodes[episodes.len() - ep_num].clone();
 
-                filename = episode.title().unwrap();
-                filename.push('.');
-                filename.push_str(&episode.extension().unwrap());
-                path = get_podcast_dir()?;
-                path.push(podcast.title());
-                path.push(filename);
-
-                if path.exists() {
-                    launch_player(path.to_str().unwrap())?;
-                } else {
-                    launch_player(episode.url().unwrap())?;
-                }
+                play_episode(state, subscription, &podcast, &episode)?;
                 return Ok(());
             }
         }
@@ -156,7 +195,7 @@ pub fn play_episode_by_name(state: &State, p_search: &str, ep_string: &str) -> R
             let mut content: Vec<u8> = Vec::new();
             file.read_to_end(&mut content).unwrap();
 
-            let podcast = Podcast::from(Channel::read_from(content.as_slice()).unwrap());
+            let podcast = Podcast::parse(&content)?;
             let episodes = podcast.episodes();
             let filtered_episodes: Vec<&Episode> = episodes
                 .iter()
@@ -168,16 +207,7 @@ pub fn play_episode_by_name(state: &State, p_search: &str, ep_string: &str) -> R
                 })
                 .collect();
             if let Some(episode) = filtered_episodes.first() {
-                filename = episode.title().unwrap();
-                filename.push_str(&episode.extension().unwrap());
-                path = get_podcast_dir()?;
-                path.push(podcast.title());
-                path.push(filename);
-                if path.exists() {
-                    launch_player(path.to_str().unwrap())?;
-                } else {
-                    launch_player(episode.url().unwrap())?;
-                }
+                play_episode(state, subscription, &podcast, episode)?;
             }
             return Ok(());
         }
@@ -0,0 +1,172 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use regex::{Captures, Regex};
+
+use std::fmt;
+
+lazy_static! {
+    static ref DECLARED_ENCODING: Regex =
+        Regex::new(r#"^\s*<\?xml[^>]*\sencoding\s*=\s*["']([^"']+)["']"#).unwrap();
+    static ref AMPERSAND: Regex =
+        Regex::new(r"&((amp|lt|gt|quot|apos);|#[0-9]+;|#[xX][0-9a-fA-F]+;)?").unwrap();
+}
+
+/// A problem with a feed that was worked around so it could be read
+#[derive(Clone, Debug, PartialEq)]
+pub enum Repair {
+    ByteOrderMark,
+    Transcoded(&'static str),
+    InvalidCharacters,
+    ControlCharacters,
+    Ampersands,
+    Truncated,
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Repair::ByteOrderMark => write!(f, "removed a byte order mark"),
+            Repair::Transcoded(encoding) => write!(f, "converted from {} to UTF-8", encoding),
+            Repair::InvalidCharacters => write!(f, "replaced bytes that aren't valid text"),
+            Repair::ControlCharacters => write!(f, "removed control characters"),
+            Repair::Ampersands => write!(f, "escaped stray ampersands"),
+            Repair::Truncated => write!(f, "closed a truncated document"),
+        }
+    }
+}
+
+/// Fixes the common ways feeds are malformed, returning the feed as UTF-8 and what was fixed
+pub fn repair(content: &[u8]) -> (String, Vec<Repair>) {
+    let mut repairs = vec![];
+
+    let (encoding, bom_length) = match Encoding::for_bom(content) {
+        Some(bom) => {
+            repairs.push(Repair::ByteOrderMark);
+            bom
+        }
+        None => (declared_encoding(content).unwrap_or(UTF_8), 0),
+    };
+    let (text, had_errors) = encoding.decode_without_bom_handling(&content[bom_length..]);
+    let mut text = text.into_owned();
+    if encoding != UTF_8 {
+        repairs.push(Repair::Transcoded(encoding.name()));
+        if let Some(declared) = DECLARED_ENCODING.captures(&text).and_then(|c| c.get(1)) {
+            text.replace_range(declared.range(), "UTF-8");
+        }
+    }
+    if had_errors {
+        repairs.push(Repair::InvalidCharacters);
+    }
+
+    if text.contains(is_control_character) {
+        text.retain(|c| !is_control_character(c));
+        repairs.push(Repair::ControlCharacters);
+    }
+
+    // The rest only applies to XML
+    if !text.trim_start().starts_with('<') {
+        return (text, repairs);
+    }
+
+    let escaped = escape_ampersands(&text);
+    if escaped != text {
+        text = escaped;
+        repairs.push(Repair::Ampersands);
+    }
+
+    // Keep the items that made it, if the feed was cut off
+    if text.contains("<rss") && !text.trim_end().ends_with("</rss>") {
+        if let Some(end) = text.rfind("</item>") {
+            text.truncate(end + "</item>".len());
+            text.push_str("</channel></rss>");
+            repairs.push(Repair::Truncated);
+        }
+    }
+
+    (text, repairs)
+}
+
+/// The encoding named in the XML declaration. UTF-16 is left to the byte order mark,
+/// since a declaration we can read as ASCII means the document isn't UTF-16.
+fn declared_encoding(content: &[u8]) -> Option<&'static Encoding> {
+    let start = String::from_utf8_lossy(&content[..content.len().min(200)]).into_owned();
+    let label = DECLARED_ENCODING.captures(&start)?.get(1)?.as_str().trim();
+    Encoding::for_label(label.as_bytes())
+        .filter(|encoding| *encoding != UTF_16LE && *encoding != UTF_16BE)
+}
+
+/// Characters that aren't allowed anywhere in an XML document
+fn is_control_character(c: char) -> bool {
+    matches!(c, '\u{0}'..='\u{8}' | '\u{B}' | '\u{C}' | '\u{E}'..='\u{1F}' | '\u{FFFE}' | '\u{FFFF}')
+}
+
+/// Escapes the ampersands that don't start an entity XML knows, leaving CDATA sections alone
+fn escape_ampersands(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("<![CDATA[") {
+        let end = rest[start..]
+            .find("]]>")
+            .map(|end| start + end + "]]>".len())
+            .unwrap_or(rest.len());
+        result.push_str(&escape_outside_cdata(&rest[..start]));
+        result.push_str(&rest[start..end]);
+        rest = &rest[end..];
+    }
+    result.push_str(&escape_outside_cdata(rest));
+    result
+}
+
+fn escape_outside_cdata(text: &str) -> String {
+    AMPERSAND
+        .replace_all(text, |caps: &Captures| match caps.get(1) {
+            Some(_) => caps[0].to_string(),
+            None => "&amp;".to_string(),
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repair_encoding() {
+        let mut feed =
+            b"\xEF\xBB\xBF<?xml version=\"1.0\" encoding=\"utf-8\"?><rss><channel><title>Caf"
+                .to_vec();
+        feed.extend(b"\xE9</title></channel></rss>");
+        let (text, repairs) = repair(&feed);
+        assert!(text.contains("Caf\u{FFFD}"));
+        assert_eq!(
+            repairs,
+            vec![Repair::ByteOrderMark, Repair::InvalidCharacters]
+        );
+
+        let feed = b"<?xml version='1.0' encoding='ISO-8859-1'?><rss><channel><title>Caf\xE9</title></channel></rss>";
+        let (text, repairs) = repair(feed);
+        assert_eq!(
+            text,
+            "<?xml version='1.0' encoding='UTF-8'?><rss><channel><title>Café</title></channel></rss>"
+        );
+        assert_eq!(repairs, vec![Repair::Transcoded("windows-1252")]);
+    }
+
+    #[test]
+    fn test_repair_markup() {
+        let feed = "<rss><channel><title>Q&A &amp; more\u{1}</title><description><![CDATA[a && b]]></description><item><title>&#233; &nbsp;</title></item><item><title>Cut";
+        let (text, repairs) = repair(feed.as_bytes());
+        assert_eq!(
+            text,
+            "<rss><channel><title>Q&amp;A &amp; more</title><description><![CDATA[a && b]]></description><item><title>&#233; &amp;nbsp;</title></item></channel></rss>"
+        );
+        assert_eq!(
+            repairs,
+            vec![
+                Repair::ControlCharacters,
+                Repair::Ampersands,
+                Repair::Truncated
+            ]
+        );
+        assert_eq!(repair(br#"{"title": "A & B"}"#).1, vec![]);
+    }
+}
@@ -1,20 +1,22 @@
 use super::actions::*;
 use super::utils::*;
-use anyhow::Result;
-use core::ops::Deref;
+use anyhow::{anyhow, Context, Result};
 
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
 use std::fs::{self, File};
 use std::io::{self, BufReader, BufWriter, Write};
+use std::time::Duration;
 
-use crate::{download, utils};
-use bloom::ASMS;
+use crate::auth::{self, Auth};
+use crate::repair::Repair;
+use crate::{download, feed, transcript, utils};
 use chrono::prelude::*;
 use regex::Regex;
-use reqwest::header;
-use rss::{Channel, Item};
+use reqwest::{header, Method, Url};
 use semver_parser::version;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[cfg(target_os = "macos")]
 const ESCAPE_REGEX: &str = r"/";
@@ -23,6 +25,9 @@ const ESCAPE_REGEX: &str = r"/";
 #[cfg(target_os = "windows")]
 const ESCAPE_REGEX: &str = r#"[\\/:*?"<>|]"#;
 
+/// How many pages of a paged feed we follow, in case its links go round in circles
+const MAX_FEED_PAGES: usize = 1000;
+
 lazy_static! {
     static ref FILENAME_ESCAPE: Regex = Regex::new(ESCAPE_REGEX).unwrap();
 }
@@ -35,6 +40,20 @@ pub struct Config {
     pub download_subscription_limit: Option<i64>,
     pub quiet: Option<bool>,
     pub filename_pattern: Option<String>,
+    /// MIME types to pick among an episode's media files, most preferred first
+    pub preferred_types: Option<Vec<String>>,
+    /// The highest bitrate to pick, in kbit/s, unless nothing else is available
+    pub max_bitrate: Option<u64>,
+    /// How many episodes are downloaded at once
+    pub max_concurrent_downloads: Option<usize>,
+    /// How many of the episodes downloaded at once may come from the same host
+    pub max_downloads_per_host: Option<usize>,
+    /// How many times a failed feed fetch or download is retried
+    pub max_retries: Option<u32>,
+    /// The most bytes per second to download in total, like `2M`
+    pub max_download_rate: Option<String>,
+    /// The most bytes per second to download from any one host
+    pub max_download_rate_per_host: Option<String>,
 }
 
 impl Default for Config {
@@ -44,6 +63,13 @@ impl Default for Config {
             download_subscription_limit: Some(1),
             quiet: Some(false),
             filename_pattern: Some("{number}-{title}".to_string()),
+            preferred_types: None,
+            max_bitrate: None,
+            max_concurrent_downloads: Some(download::CONCURRENT_DOWNLOADS),
+            max_downloads_per_host: Some(download::DOWNLOADS_PER_HOST),
+            max_retries: Some(utils::RETRIES),
+            max_download_rate: None,
+            max_download_rate_per_host: None,
         }
     }
 }
@@ -58,20 +84,376 @@ impl Config {
         }
         Ok(None)
     }
+
+    pub fn retries(&self) -> u32 {
+        self.max_retries.unwrap_or(utils::RETRIES)
+    }
+}
+
+/// Settings for a single subscription, each taking precedence over the global Config
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SubscriptionConfig {
+    pub auto_download_limit: Option<i64>,
+    pub download_subscription_limit: Option<i64>,
+    pub filename_pattern: Option<String>,
+    pub download_dir: Option<PathBuf>,
+    pub playback_speed: Option<f64>,
+    pub never_auto_download: Option<bool>,
+    pub preferred_types: Option<Vec<String>>,
+    pub max_bitrate: Option<u64>,
+}
+
+impl SubscriptionConfig {
+    pub const KEYS: [&'static str; 8] = [
+        "auto_download_limit",
+        "download_subscription_limit",
+        "filename_pattern",
+        "download_dir",
+        "playback_speed",
+        "never_auto_download",
+        "preferred_types",
+        "max_bitrate",
+    ];
+
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(match key {
+            "auto_download_limit" => self.auto_download_limit.map(|v| v.to_string()),
+            "download_subscription_limit" => {
+                self.download_subscription_limit.map(|v| v.to_string())
+            }
+            "filename_pattern" => self.filename_pattern.clone(),
+            "download_dir" => self
+                .download_dir
+                .as_ref()
+                .map(|v| v.to_string_lossy().into_owned()),
+            "playback_speed" => self.playback_speed.map(|v| v.to_string()),
+            "never_auto_download" => self.never_auto_download.map(|v| v.to_string()),
+            "preferred_types" => self.preferred_types.as_ref().map(|v| v.join(",")),
+            "max_bitrate" => self.max_bitrate.map(|v| v.to_string()),
+            other => return Err(anyhow!("Unknown setting: {}", other)),
+        })
+    }
+
+    /// Sets the given setting, or clears it to fall back to the global value if `value` is None
+    pub fn set(&mut self, key: &str, value: Option<&str>) -> Result<()> {
+        match key {
+            "auto_download_limit" => {
+                self.auto_download_limit = value.map(str::parse).transpose()?
+            }
+            "download_subscription_limit" => {
+                self.download_subscription_limit = value.map(str::parse).transpose()?
+            }
+            "filename_pattern" => self.filename_pattern = value.map(String::from),
+            "download_dir" => self.download_dir = value.map(PathBuf::from),
+            "playback_speed" => self.playback_speed = value.map(str::parse).transpose()?,
+            "never_auto_download" => {
+                self.never_auto_download = value.map(str::parse).transpose()?
+            }
+            // A comma separated list, like `audio/opus,audio/mpeg`
+            "preferred_types" => {
+                self.preferred_types = value.map(|v| {
+                    v.split(',')
+                        .map(str::trim)
+                        .filter(|t| !t.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+            }
+            "max_bitrate" => self.max_bitrate = value.map(str::parse).transpose()?,
+            other => return Err(anyhow!("Unknown setting: {}", other)),
+        }
+        Ok(())
+    }
+}
+
+/// Whether a subscription is checked for new episodes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubscriptionStatus {
+    #[default]
+    Active,
+    /// Kept, but not checked for new episodes
+    Paused,
+    /// Like paused, and also hidden from the list of subscriptions
+    Archived,
 }
 
 /// This is persisted to disk and represents each subscription and it's last known state
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Subscription {
     pub title: String,
     pub url: String,
     pub num_episodes: usize,
+    /// The ids of the episodes we've seen, or None for a subscription persisted before
+    /// these were tracked, which only knows `num_episodes`
+    #[serde(default)]
+    pub seen_episodes: Option<BTreeSet<String>>,
+    #[serde(default)]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub last_modified: Option<String>,
+    #[serde(default)]
+    pub guid: Option<String>,
+    #[serde(default)]
+    pub config: SubscriptionConfig,
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    #[serde(default)]
+    pub status: SubscriptionStatus,
+    #[serde(default)]
+    pub auth: Vec<Auth>,
 }
 
 impl Subscription {
     pub fn title(&self) -> &str {
         &self.title
     }
+
+    /// Headers carrying the credentials for this subscription's feed and episodes
+    pub fn auth_headers(&self) -> Result<header::HeaderMap> {
+        auth::headers(&self.auth)
+    }
+
+    /// Where the feed of this subscription is cached
+    pub fn xml_path(&self) -> Result<PathBuf> {
+        let mut path = get_xml_dir()?;
+        path.push(utils::append_extension(&self.title, "xml"));
+        Ok(path)
+    }
+
+    /// Renames the subscription, moving its episodes and cached feed along with it
+    pub fn rename(&mut self, title: &str) -> Result<()> {
+        let title = title.trim();
+        if title.is_empty() || title.starts_with('.') || FILENAME_ESCAPE.is_match(title) {
+            return Err(anyhow!("'{}' can't be used as a directory name", title));
+        }
+
+        let renamed = Subscription {
+            title: title.to_string(),
+            ..self.clone()
+        };
+        let (old_dir, new_dir) = (self.download_dir()?, renamed.download_dir()?);
+        let (old_xml, new_xml) = (self.xml_path()?, renamed.xml_path()?);
+
+        // Episodes only move if they're in the default location, which is named after the podcast
+        let move_dir = self.config.download_dir.is_none() && old_dir.exists();
+        if move_dir && new_dir.exists() {
+            return Err(anyhow!("{:?} already exists", new_dir));
+        }
+        if old_xml.exists() && new_xml.exists() {
+            return Err(anyhow!("{:?} already exists", new_xml));
+        }
+
+        if move_dir {
+            fs::rename(&old_dir, &new_dir)?;
+        }
+        if old_xml.exists() {
+            if let Err(err) = fs::rename(&old_xml, &new_xml) {
+                // Put the episodes back so we don't end up half renamed
+                if move_dir {
+                    fs::rename(&new_dir, &old_dir).ok();
+                }
+                return Err(err.into());
+            }
+        }
+        self.title = renamed.title;
+        Ok(())
+    }
+
+    /// Where episodes of this subscription are downloaded to
+    pub fn download_dir(&self) -> Result<PathBuf> {
+        match &self.config.download_dir {
+            Some(dir) => Ok(dir.clone()),
+            None => {
+                let mut path = get_podcast_dir()?;
+                path.push(&self.title);
+                Ok(path)
+            }
+        }
+    }
+
+    pub fn filename_pattern<'a>(&'a self, config: &'a Config) -> Option<&'a str> {
+        self.config
+            .filename_pattern
+            .as_deref()
+            .or(config.filename_pattern.as_deref())
+    }
+
+    /// Where the given episode is saved, named according to the filename pattern
+    pub fn episode_path(
+        &self,
+        config: &Config,
+        podcast: &Podcast,
+        episode: &Episode,
+    ) -> Result<Option<PathBuf>> {
+        let title = match episode.title() {
+            Some(title) => title,
+            None => return Ok(None),
+        };
+        let mut filename;
+        if let Some(pattern) = self.filename_pattern(config) {
+            filename = pattern.replace("{title}", &title);
+            // Prefer the publisher's numbering, falling back to the position in the feed
+            let number = episode.number().map(|n| n as usize).or_else(|| {
+                podcast
+                    .episodes()
+                    .iter()
+                    .rev()
+                    .position(|e| e == episode)
+                    .map(|x| x + 1)
+            });
+            filename = filename.replace(
+                "{number}",
+                &number.map(|n| n.to_string()).unwrap_or_default(),
+            );
+        } else {
+            filename = title;
+        }
+
+        if let Some(ext) = self
+            .sources(config, episode)
+            .first()
+            .and_then(Source::extension)
+        {
+            filename = utils::append_extension(&filename, &ext);
+        }
+        let mut path = self.download_dir()?;
+        path.push(&filename);
+        Ok(Some(path))
+    }
+
+    /// Where the given episode was saved, if it has been downloaded. It may have been saved
+    /// from any of its sources, each with its own extension.
+    pub fn saved_path(
+        &self,
+        config: &Config,
+        podcast: &Podcast,
+        episode: &Episode,
+    ) -> Result<Option<PathBuf>> {
+        let path = match self.episode_path(config, podcast, episode)? {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let sources = self.sources(config, episode);
+        Ok(sources
+            .iter()
+            .map(|source| source_path(&path, &sources, source))
+            .chain(std::iter::once(path.clone()))
+            .find(|path| path.exists()))
+    }
+
+    /// The episode's media files, best match for the preferred types and maximum bitrate first.
+    /// Files over the maximum bitrate are still used if there's nothing else.
+    pub fn sources(&self, config: &Config, episode: &Episode) -> Vec<Source> {
+        let preferred = self
+            .config
+            .preferred_types
+            .as_ref()
+            .or(config.preferred_types.as_ref())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let max_bitrate = self.config.max_bitrate.or(config.max_bitrate);
+
+        let mut sources = episode.sources();
+        sources.retain(|source| utils::may_link_to(&self.url, &source.url));
+        // The sort is stable, so ties keep the order of the feed
+        sources.sort_by_key(|source| {
+            let too_high = match (max_bitrate, source.bitrate) {
+                (Some(max), Some(bitrate)) => bitrate > max * 1000,
+                _ => false,
+            };
+            let rank = source
+                .mime_type
+                .as_deref()
+                .and_then(|mime_type| {
+                    preferred
+                        .iter()
+                        .position(|pattern| mime_type_matches(pattern, mime_type))
+                })
+                .unwrap_or(preferred.len());
+            (too_high, rank)
+        });
+        sources
+    }
+
+    /// How many episodes to download when subscribing
+    pub fn auto_download_limit(&self, config: &Config) -> i64 {
+        if self.config.never_auto_download.unwrap_or(false) {
+            return 0;
+        }
+        self.config
+            .auto_download_limit
+            .or(config.auto_download_limit)
+            .unwrap_or(1)
+    }
+
+    /// How many new episodes to download when refreshing, None meaning all of them
+    pub fn download_subscription_limit(&self, config: &Config) -> Option<i64> {
+        if self.config.never_auto_download.unwrap_or(false) {
+            return Some(0);
+        }
+        self.config
+            .download_subscription_limit
+            .or(config.download_subscription_limit)
+    }
+
+    /// Returns the episodes we haven't seen before, newest first
+    pub fn new_episodes(&self, episodes: &[Episode]) -> Vec<Episode> {
+        // Subscriptions persisted before we tracked episode ids only know how many
+        // episodes they had, which were the oldest ones in the feed
+        let seen: BTreeSet<String> = match &self.seen_episodes {
+            Some(seen) => seen.clone(),
+            None => episodes[episodes.len().saturating_sub(self.num_episodes)..]
+                .iter()
+                .map(Episode::id)
+                .collect(),
+        };
+
+        episodes
+            .iter()
+            .filter(|ep| !seen.contains(&ep.id()))
+            .cloned()
+            .collect()
+    }
+
+    /// Points the subscription at the feed's new home, letting the user know.
+    /// A new home that isn't an http(s) URL is reported and ignored.
+    pub fn move_to(&mut self, url: &str) {
+        if self.url != url && check_move(&self.title, &self.url, url) {
+            println!("{} has moved: {} -> {}", self.title, self.url, url);
+            self.url = url.to_string();
+        }
+    }
+
+    /// Remembers the validators needed to make the next feed fetch conditional
+    pub fn set_cache_headers(&mut self, headers: &header::HeaderMap) {
+        let value = |name| {
+            headers
+                .get(name)
+                .and_then(|value: &header::HeaderValue| value.to_str().ok())
+                .map(String::from)
+        };
+        self.etag = value(header::ETAG);
+        self.last_modified = value(header::LAST_MODIFIED);
+    }
+
+    /// Records every given episode as seen
+    pub fn mark_seen(&mut self, episodes: &[Episode]) {
+        self.seen_episodes
+            .get_or_insert_with(BTreeSet::new)
+            .extend(episodes.iter().map(Episode::id));
+        self.num_episodes = episodes.len();
+    }
+
+    /// Records whether the episode with the given id has been seen
+    pub fn set_seen(&mut self, id: &str, seen: bool) {
+        let seen_episodes = self.seen_episodes.get_or_insert_with(BTreeSet::new);
+        if seen {
+            seen_episodes.insert(id.to_string());
+        } else {
+            seen_episodes.remove(id);
+        }
+    }
 }
 
 /// This struct is what is serialized to disk
@@ -132,7 +514,7 @@ impl From<ParseState> for State {
             last_run_time: internal_state.last_run_time.unwrap_or_else(Utc::now),
             config: internal_state.config.unwrap_or_default(),
             subscriptions: internal_state.subscriptions.unwrap_or_default(),
-            client: reqwest::Client::new(),
+            client: utils::client(),
         }
     }
 }
@@ -164,7 +546,8 @@ impl State {
                 .num_days()
             {
                 state.check_for_update().await?;
-                state.update_rss().await?;
+                // Only the command that asked for downloads fails because of them
+                state.update_rss(None, false).await?;
             }
 
             // Update last run time and persist config
@@ -178,63 +561,171 @@ impl State {
                 last_run_time: Utc::now(),
                 subscriptions: Vec::new(),
                 config,
-                client: reqwest::Client::new(),
+                client: utils::client(),
             })
         }
     }
 
-    pub async fn subscribe(&mut self, url: &str) -> Result<()> {
-        // Make a bloom filter and populate it with subscription titles
-        let existing_subscriptions = if self.subscriptions.is_empty() {
-            10
-        } else {
-            self.subscriptions.len()
-        };
-
-        let mut bloom_filter = bloom::BloomFilter::with_rate(0.1, existing_subscriptions as u32);
-        for sub in &self.subscriptions {
-            bloom_filter.insert(&sub.title);
-        }
+    /// Finds an existing subscription to the same podcast, by feed URL, `podcast:guid` or title
+    pub fn find_subscription(
+        &self,
+        urls: &[&str],
+        guid: Option<&str>,
+        title: &str,
+    ) -> Option<usize> {
+        let urls: Vec<String> = urls.iter().map(|url| normalize_url(url)).collect();
+        self.subscriptions.iter().position(|sub| {
+            urls.contains(&normalize_url(&sub.url))
+                || (guid.is_some() && sub.guid.as_deref() == guid)
+                || sub.title == title
+        })
+    }
 
+    /// Subscribes to the given feed, returning the index of the subscription and whether
+    /// every episode downloaded with it was. If we're already subscribed to it at another
+    /// URL, `ask` says whether to ask about switching.
+    pub async fn subscribe(
+        &mut self,
+        url: &str,
+        auth: &[Auth],
+        auto_download: bool,
+        ask: bool,
+    ) -> Result<(usize, bool)> {
         // Fetch provided podcast RSS feed
-        let resp = reqwest::get(url).await?.bytes().await?;
+        let feed = utils::fetch_feed(url, auth::headers(auth)?, self.config.retries()).await?;
+        let resp = feed.response.error_for_status()?;
+        let headers = resp.headers().clone();
+        let page_url = feed.url;
+        let resp = resp.bytes().await?;
 
         // Parse the response into a podcast struct
-        let channel = Channel::read_from(BufReader::new(&resp[..]))?;
-        let podcast = Podcast::from(channel);
+        let (mut podcast, repairs) = Podcast::parse_lenient(&resp)?;
+        report_repairs(podcast.title(), &repairs);
+
+        let feed_url = feed
+            .moved_to
+            .as_deref()
+            .or_else(|| {
+                podcast
+                    .new_feed_url()
+                    .filter(|new_url| check_move(podcast.title(), url, new_url))
+            })
+            .unwrap_or(url)
+            .to_string();
 
         // Check if the podcast already exists in our subscriptions
-        if !bloom_filter.contains(&podcast.title()) {
-            self.subscriptions.push(Subscription {
-                title: String::from(podcast.title()),
-                url: String::from(url),
-                num_episodes: podcast.episodes().len(),
-            });
+        let guid = podcast.guid();
+        if let Some(index) =
+            self.find_subscription(&[url, &feed_url], guid.as_deref(), podcast.title())
+        {
+            let existing = &mut self.subscriptions[index];
+            println!("You are already subscribed to {}", existing.title());
+            if normalize_url(&existing.url) == normalize_url(&feed_url) {
+                return Ok((index, true));
+            }
+            if ask {
+                print!(
+                    "It is subscribed at {}, would you like to use {} instead? (y/n): ",
+                    existing.url, feed_url
+                );
+                io::stdout().flush().ok();
+                let mut input = String::new();
+                io::stdin().read_line(&mut input)?;
+                if input.to_lowercase().trim() == "y" {
+                    existing.move_to(&feed_url);
+                }
+            } else {
+                println!("It is subscribed at {}, not {}", existing.url, feed_url);
+            }
+            return Ok((index, true));
         }
-        let episodes = download::download_rss(self, url).await?;
-        download::download_episodes(episodes).await?;
-        Ok(())
+
+        let mut subscription = Subscription {
+            title: String::from(podcast.title()),
+            url: String::from(url),
+            guid,
+            auth: auth.to_vec(),
+            ..Default::default()
+        };
+        subscription.move_to(&feed_url);
+        subscription.set_cache_headers(&headers);
+        podcast
+            .fetch_older_pages(
+                &page_url,
+                &subscription.auth_headers()?,
+                self.config.retries(),
+            )
+            .await;
+        subscription.mark_seen(&podcast.episodes());
+        let file = File::create(subscription.xml_path()?)?;
+        podcast.write_to(BufWriter::new(file))?;
+        self.subscriptions.push(subscription.clone());
+
+        let index = self.subscriptions.len() - 1;
+        let mut all_downloaded = true;
+        if auto_download {
+            let episodes = download::download_rss(self, &subscription, &podcast).await?;
+            let ids: Vec<String> = episodes.iter().map(|episode| episode.id.clone()).collect();
+            let downloaded = download::download_episodes(episodes, &self.config).await?;
+            // A failed download is left unseen, so the next refresh tries it again
+            for (id, downloaded) in ids.iter().zip(downloaded) {
+                if !downloaded {
+                    self.subscriptions[index].set_seen(id, false);
+                    all_downloaded = false;
+                }
+            }
+        }
+        Ok((index, all_downloaded))
     }
 
-    pub async fn update_rss(&mut self) -> Result<()> {
+    /// Checks every subscription for new episodes, or only those with the given tag.
+    /// A full refresh also fetches every older page of paged feeds. Returns whether every
+    /// new episode was downloaded.
+    pub async fn update_rss(&mut self, tag: Option<&str>, full: bool) -> Result<bool> {
         println!("Checking for new episodes...");
         let mut d_vec = vec![];
-        for (index, sub) in self.subscriptions.iter().enumerate() {
-            d_vec.push(update_subscription(self, index, sub, &self.config));
+        for (index, sub) in self
+            .subscriptions
+            .iter()
+            .enumerate()
+            .filter(|(_, sub)| sub.status == SubscriptionStatus::Active)
+            .filter(|(_, sub)| tag.map(|tag| sub.tags.contains(tag)).unwrap_or(true))
+        {
+            let update = update_subscription(self, index, sub, &self.config, full);
+            d_vec.push(async move { (sub.title(), update.await) });
         }
         let new_subscriptions = futures::future::join_all(d_vec).await;
-        for c in &new_subscriptions {
+        let mut updated = vec![];
+        let mut downloads = vec![];
+        // Which subscription each download is for
+        let mut owners = vec![];
+        for (title, c) in new_subscriptions {
             match c {
-                Ok([index, new_ep_count]) => {
-                    self.subscriptions[*index].num_episodes = *new_ep_count;
-                }
-                Err(err) => {
-                    println!("Error: {}", err);
+                Ok((index, subscription, mut new_downloads)) => {
+                    updated.push((index, subscription));
+                    owners.extend(
+                        new_downloads
+                            .iter()
+                            .map(|episode| (index, episode.id.clone())),
+                    );
+                    downloads.append(&mut new_downloads);
                 }
+                Err(err) => println!("Error refreshing {}: {:#}", title, err),
+            }
+        }
+        for (index, subscription) in updated {
+            self.subscriptions[index] = subscription;
+        }
+        // Downloaded together, so the limits on concurrent downloads hold across podcasts
+        let downloaded = download::download_episodes(downloads, &self.config).await?;
+        let all_downloaded = downloaded.iter().all(|downloaded| *downloaded);
+        for ((index, id), downloaded) in owners.into_iter().zip(downloaded) {
+            if downloaded {
+                self.subscriptions[index].set_seen(&id, true);
             }
         }
         println!("Done.");
-        Ok(())
+        Ok(all_downloaded)
     }
 
     pub async fn check_for_update(&self) -> Result<()> {
@@ -273,97 +764,263 @@ impl State {
 /// Represent an intention to download a file
 #[derive(Clone, Debug, PartialEq)]
 pub struct Download {
+    /// The id of the episode, as in `Episode::id`
+    pub id: String,
     pub title: String,
     pub path: PathBuf,
-    pub url: String,
+    /// Where the episode can be downloaded from, in order of preference
+    pub sources: Vec<Source>,
     pub size: u64,
+    pub headers: header::HeaderMap,
+    pub chapters: Option<String>,
+    pub transcript: Option<transcript::Link>,
 }
 
 impl Download {
+    /// Where the episode is saved when it's fetched from the given source, since the file
+    /// extension follows the source
+    pub fn path_for(&self, source: &Source) -> PathBuf {
+        source_path(&self.path, &self.sources, source)
+    }
+
     pub async fn new(
         state: &State,
+        sub: &Subscription,
         podcast: &Podcast,
         episode: &Episode,
     ) -> Result<Option<Download>> {
-        let mut path = utils::get_podcast_dir()?;
-        path.push(podcast.title());
-        utils::create_dir_if_not_exist(&path)?;
-        let pattern = state.config.filename_pattern.as_ref();
-        if let (Some(title), Some(url)) = (episode.title(), episode.url()) {
-            let mut filename;
-            if let Some(pattern) = pattern {
-                filename = pattern.replace("{title}", &title);
-                filename = filename.replace(
-                    "{number}",
-                    &podcast
-                        .episodes()
-                        .iter()
-                        .rev()
-                        .position(|e| e == episode)
-                        .map(|x| (x + 1).to_string())
-                        .unwrap_or_else(|| "".into()),
-                );
-            } else {
-                filename = title.clone();
+        utils::create_dir_if_not_exist(&sub.download_dir()?)?;
+        let sources = sub.sources(&state.config, episode);
+        if let (Some(title), Some(source), Some(path)) = (
+            episode.title(),
+            sources.first().cloned(),
+            sub.episode_path(&state.config, podcast, episode)?,
+        ) {
+            let mut download = Download {
+                id: episode.id(),
+                title,
+                path,
+                sources,
+                size: 0,
+                headers: sub.auth_headers()?,
+                chapters: episode
+                    .chapters_url()
+                    .filter(|url| utils::may_link_to(&sub.url, url))
+                    .map(String::from),
+                transcript: episode
+                    .transcript()
+                    .filter(|link| utils::may_link_to(&sub.url, &link.url)),
+            };
+            if sub.saved_path(&state.config, podcast, episode)?.is_some() {
+                return Ok(None);
             }
 
-            if let Some(ext) = episode.extension() {
-                filename = utils::append_extension(&filename, &ext);
-            }
-            path.push(&filename);
-
-            let head_resp = state.client.head(url).send().await?;
-            let total_size = head_resp
-                .headers()
-                .get(header::CONTENT_LENGTH)
-                .and_then(|ct_len| ct_len.to_str().ok())
-                .and_then(|ct_len| ct_len.parse().ok())
-                .unwrap_or(0);
-
-            if !path.exists() {
-                return Ok(Some(Download {
-                    title,
-                    path,
-                    url: url.into(),
-                    size: total_size,
-                }));
-            }
+            download.size = match utils::file_path(&source.url) {
+                Some(file) => file.metadata().map(|m| m.len()).unwrap_or(0),
+                // Another source is tried if this one doesn't work out, so an error isn't fatal yet
+                None => utils::send(
+                    &state.client,
+                    Method::HEAD,
+                    &source.url,
+                    download.headers.clone(),
+                )
+                .await
+                .ok()
+                .filter(|head_resp| head_resp.status().is_success())
+                .and_then(|head_resp| {
+                    head_resp
+                        .headers()
+                        .get(header::CONTENT_LENGTH)
+                        .and_then(|ct_len| ct_len.to_str().ok())
+                        .and_then(|ct_len| ct_len.parse().ok())
+                })
+                .or(source.length)
+                .unwrap_or(0),
+            };
+            return Ok(Some(download));
         }
         Ok(None)
     }
 }
 
+/// Tells which feed needed repairs to be read, and what they were
+/// Where an episode named after its first source, at the given path, is saved when it's
+/// fetched from the given source
+fn source_path(path: &Path, sources: &[Source], source: &Source) -> PathBuf {
+    let first = sources.first().and_then(Source::extension);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    // The path ends in the extension of the first source, if it has one
+    let stem = first
+        .and_then(|first| name.strip_suffix(first.as_str()))
+        .filter(|stem| stem.ends_with('.'));
+    match (stem, source.extension()) {
+        (Some(stem), Some(ext)) => path.with_file_name(format!("{}{}", stem, ext)),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Whether a feed may move to the given URL, reporting it if it may not
+fn check_move(title: &str, from: &str, to: &str) -> bool {
+    let allowed = utils::may_move_to(from, to);
+    if !allowed {
+        eprintln!("Ignoring the new address given for {}: {}", title, to);
+    }
+    allowed
+}
+
+pub fn report_repairs(title: &str, repairs: &[Repair]) {
+    if !repairs.is_empty() {
+        let repairs: Vec<String> = repairs.iter().map(Repair::to_string).collect();
+        println!(
+            "Recovered malformed feed of {}: {}",
+            title,
+            repairs.join(", ")
+        );
+    }
+}
+
+/// A way to support the podcast, from `<podcast:funding>`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Funding {
+    pub url: String,
+    pub text: Option<String>,
+}
+
+/// Someone involved in making the podcast, from `<podcast:person>`
 #[derive(Clone, Debug, PartialEq)]
-pub struct Podcast(Channel);
+pub struct Person {
+    pub name: String,
+    pub role: Option<String>,
+    pub href: Option<String>,
+}
 
-impl From<Channel> for Podcast {
-    fn from(channel: Channel) -> Podcast {
-        Podcast(channel)
+/// Whether a MIME type matches a preferred type such as `audio/opus`, `audio/*` or `audio`
+fn mime_type_matches(pattern: &str, mime_type: &str) -> bool {
+    let pattern = pattern.trim().to_lowercase();
+    let mime_type = mime_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    match pattern.strip_suffix("/*") {
+        Some(kind) => mime_type.split('/').next() == Some(kind),
+        None if !pattern.contains('/') => mime_type.split('/').next() == Some(&pattern),
+        None => mime_type == pattern,
     }
 }
 
-impl Deref for Podcast {
-    type Target = Channel;
+/// A media file an episode is available as, from its `<enclosure>` or `<podcast:alternateEnclosure>`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Source {
+    pub url: String,
+    pub mime_type: Option<String>,
+    pub length: Option<u64>,
+    /// In bits per second
+    pub bitrate: Option<u64>,
+}
 
-    fn deref(&self) -> &Channel {
-        &self.0
+impl Source {
+    pub fn extension(&self) -> Option<String> {
+        match self.mime_type.as_deref().unwrap_or_default() {
+            "audio/mpeg" => Some("mp3".into()),
+            "audio/mp4" => Some("m4a".into()),
+            "audio/aac" => Some("m4a".into()),
+            "audio/ogg" => Some("ogg".into()),
+            "audio/vorbis" => Some("ogg".into()),
+            "audio/opus" => Some("opus".into()),
+            _ => find_extension(&self.url),
+        }
     }
 }
 
+/// A podcast, whichever format its feed was published in. Feeds are read into this by
+/// `feed::parse`, and cached as RSS by `feed::to_rss`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Podcast {
+    pub title: String,
+    /// The podcast's website
+    pub link: String,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub language: Option<String>,
+    /// The iTunes categories, with subcategories as `Category > Subcategory`, and any others
+    pub categories: Vec<String>,
+    pub image: Option<String>,
+    pub explicit: Option<bool>,
+    pub funding: Vec<Funding>,
+    pub persons: Vec<Person>,
+    /// Whether the publisher asks other platforms not to import the feed, and who owns it
+    pub locked: Option<(bool, Option<String>)>,
+    /// The globally unique id of the podcast, from `<podcast:guid>`
+    pub guid: Option<String>,
+    /// Where the publisher says the feed now lives, from `<itunes:new-feed-url>`
+    pub new_feed_url: Option<String>,
+    /// The link to the page with older episodes of a paged or archived feed (RFC 5005),
+    /// as written in the feed
+    pub older_page: Option<String>,
+    pub episodes: Vec<Episode>,
+}
+
 impl Podcast {
     pub fn title(&self) -> &str {
-        self.0.title()
+        &self.title
     }
 
-    #[allow(dead_code)]
+    /// The podcast's website
     pub fn url(&self) -> &str {
-        self.0.link()
+        &self.link
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn author(&self) -> Option<&str> {
+        self.author.as_deref()
+    }
+
+    pub fn language(&self) -> Option<&str> {
+        self.language.as_deref()
+    }
+
+    pub fn categories(&self) -> Vec<String> {
+        self.categories.clone()
+    }
+
+    pub fn image(&self) -> Option<&str> {
+        self.image.as_deref()
+    }
+
+    pub fn explicit(&self) -> Option<bool> {
+        self.explicit
+    }
+
+    pub fn funding(&self) -> Vec<Funding> {
+        self.funding.clone()
+    }
+
+    pub fn persons(&self) -> Vec<Person> {
+        self.persons.clone()
+    }
+
+    pub fn locked(&self) -> Option<(bool, Option<&str>)> {
+        let (locked, owner) = self.locked.as_ref()?;
+        Some((*locked, owner.as_deref()))
+    }
+
+    pub fn guid(&self) -> Option<String> {
+        self.guid.clone()
+    }
+
+    pub fn new_feed_url(&self) -> Option<&str> {
+        self.new_feed_url.as_deref()
     }
 
     #[allow(dead_code)]
     pub async fn from_url(url: &str) -> Result<Podcast> {
         let content = reqwest::get(url).await?.bytes().await?;
-        Ok(Podcast::from(Channel::read_from(&content[..])?))
+        Podcast::parse(&content)
     }
 
     pub fn from_title(title: &str) -> Result<Podcast> {
@@ -372,53 +1029,666 @@ impl Podcast {
         filename.push_str(".xml");
         path.push(filename);
 
-        let file = File::open(&path)?;
-        Ok(Podcast::from(Channel::read_from(BufReader::new(file))?))
+        Podcast::parse(&fs::read(&path)?)
+    }
+
+    /// Parses an RSS, Atom or JSON feed
+    pub fn parse(content: &[u8]) -> Result<Podcast> {
+        feed::parse(content)
+    }
+
+    /// Parses a feed as published, which may need repairs first. Returns what was repaired.
+    pub fn parse_lenient(content: &[u8]) -> Result<(Podcast, Vec<Repair>)> {
+        feed::parse_lenient(content).context("Couldn't parse the feed")
+    }
+
+    /// Writes the podcast as an RSS feed
+    pub fn write_to<W: Write>(&self, writer: W) -> Result<()> {
+        feed::to_rss(self).write_to(writer)?;
+        Ok(())
     }
 
     pub fn episodes(&self) -> Vec<Episode> {
-        let mut result = Vec::new();
-        for item in self.0.items().iter().cloned() {
-            result.push(Episode::from(item));
+        self.episodes.clone()
+    }
+
+    /// The page with older episodes, resolved against the URL this page was fetched from
+    pub fn next_page(&self, page_url: &str) -> Option<String> {
+        let link = self.older_page.as_deref()?;
+        Url::parse(page_url)
+            .and_then(|base| base.join(link.trim()))
+            .map(String::from)
+            .ok()
+    }
+
+    /// Adds the episodes of another page of the feed that we don't have yet
+    pub fn merge(&mut self, page: &Podcast) {
+        let known: HashSet<&str> = self.episodes.iter().map(|ep| ep.id.as_str()).collect();
+        let older: Vec<Episode> = page
+            .episodes
+            .iter()
+            .filter(|episode| !known.contains(episode.id.as_str()))
+            .cloned()
+            .collect();
+        self.episodes.extend(older);
+    }
+
+    /// Follows the links to older pages of the feed, merging in their episodes. A page that
+    /// can't be fetched is reported, keeping the pages merged before it.
+    pub async fn fetch_older_pages(
+        &mut self,
+        feed_url: &str,
+        headers: &header::HeaderMap,
+        retries: u32,
+    ) {
+        let mut visited = HashSet::new();
+        visited.insert(feed_url.to_string());
+        let mut next = self.next_page(feed_url);
+        while let Some(url) = next.filter(|url| visited.insert(url.clone())) {
+            if visited.len() > MAX_FEED_PAGES {
+                eprintln!("Stopped after {} pages of {}", MAX_FEED_PAGES, self.title());
+                break;
+            }
+            let page = async {
+                if !utils::may_link_to(feed_url, &url) {
+                    return Err(anyhow!("{} links to a local file", feed_url));
+                }
+                let resp = utils::fetch_feed(&url, headers.clone(), retries)
+                    .await?
+                    .response
+                    .error_for_status()?;
+                Podcast::parse_lenient(&resp.bytes().await?)
+            };
+            let (page, repairs) = match page.await {
+                Ok(page) => page,
+                Err(err) => {
+                    eprintln!(
+                        "Couldn't fetch an older page of {}, {}: {:#}",
+                        self.title(),
+                        url,
+                        err
+                    );
+                    break;
+                }
+            };
+            report_repairs(self.title(), &repairs);
+            self.merge(&page);
+            next = page.next_page(&url);
+        }
+    }
+
+    /// Finds an episode by its number in `podcast ls`, or else by a part of its title
+    pub fn find_episode(&self, e_search: &str) -> Option<Episode> {
+        let episodes = self.episodes();
+        if let Ok(ep_num) = e_search.parse::<usize>() {
+            if 0 < ep_num && ep_num <= episodes.len() {
+                return Some(episodes[episodes.len() - ep_num].clone());
+            }
         }
-        result
+        episodes.into_iter().find(|ep| {
+            ep.title()
+                .map(|title| title.to_lowercase().contains(&e_search.to_lowercase()))
+                .unwrap_or(false)
+        })
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
-pub struct Episode(Item);
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EpisodeType {
+    Full,
+    Trailer,
+    Bonus,
+}
 
-impl From<Item> for Episode {
-    fn from(item: Item) -> Episode {
-        Episode(item)
+impl fmt::Display for EpisodeType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EpisodeType::Full => write!(f, "full"),
+            EpisodeType::Trailer => write!(f, "trailer"),
+            EpisodeType::Bonus => write!(f, "bonus"),
+        }
     }
 }
 
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Episode {
+    /// A stable identity for the episode, used to tell which episodes are new.
+    /// It's the `<guid>`, else the enclosure URL, else the title and publish date.
+    pub id: String,
+    pub title: Option<String>,
+    pub published: Option<DateTime<FixedOffset>>,
+    /// The `<enclosure>`
+    pub enclosure: Option<Source>,
+    /// Other media files of the episode, from `<podcast:alternateEnclosure>`
+    pub alternates: Vec<Source>,
+    /// Where the episode's JSON chapters live, from `<podcast:chapters>`
+    pub chapters_url: Option<String>,
+    /// The transcripts we can read, from `<podcast:transcript>`
+    pub transcripts: Vec<transcript::Link>,
+    pub duration: Option<Duration>,
+    pub season: Option<u32>,
+    /// The publisher's number for the episode, from `<itunes:episode>`
+    pub number: Option<u32>,
+    pub episode_type: Option<EpisodeType>,
+    pub explicit: Option<bool>,
+    /// The episode's own artwork
+    pub image: Option<String>,
+}
+
 impl Episode {
+    pub fn id(&self) -> String {
+        self.id.clone()
+    }
+
     pub fn title(&self) -> Option<String> {
         Some(
             FILENAME_ESCAPE
-                .replace_all(self.0.title()?, "_")
+                .replace_all(self.title.as_deref()?, "_")
                 .to_string(),
         )
     }
 
-    pub fn url(&self) -> Option<&str> {
-        match self.0.enclosure() {
-            Some(val) => Some(val.url()),
-            None => None,
+    pub fn chapters_url(&self) -> Option<&str> {
+        self.chapters_url.as_deref()
+    }
+
+    /// The transcript in the most useful format we can read
+    pub fn transcript(&self) -> Option<transcript::Link> {
+        self.transcripts
+            .iter()
+            .min_by_key(|link| link.format)
+            .cloned()
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    pub fn season(&self) -> Option<u32> {
+        self.season
+    }
+
+    pub fn number(&self) -> Option<u32> {
+        self.number
+    }
+
+    pub fn episode_type(&self) -> Option<EpisodeType> {
+        self.episode_type
+    }
+
+    pub fn explicit(&self) -> Option<bool> {
+        self.explicit
+    }
+
+    pub fn pub_date(&self) -> Option<DateTime<FixedOffset>> {
+        self.published
+    }
+
+    #[allow(dead_code)]
+    pub fn image(&self) -> Option<&str> {
+        self.image.as_deref()
+    }
+
+    /// Every media file the episode is available as that we can fetch, the enclosure first
+    pub fn sources(&self) -> Vec<Source> {
+        let mut sources: Vec<Source> = vec![];
+        for source in self.enclosure.iter().chain(&self.alternates) {
+            // Alternates may also be on IPFS, torrents and so on
+            if !["http://", "https://", "file://"]
+                .iter()
+                .any(|scheme| source.url.to_lowercase().starts_with(scheme))
+            {
+                continue;
+            }
+            // The enclosure is usually listed as an alternate too, with more details
+            match sources.iter_mut().find(|known| known.url == source.url) {
+                Some(known) => {
+                    known.mime_type = known.mime_type.take().or_else(|| source.mime_type.clone());
+                    known.length = known.length.or(source.length);
+                    known.bitrate = known.bitrate.or(source.bitrate);
+                }
+                None => sources.push(source.clone()),
+            }
+        }
+        sources
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(id: &str) -> Episode {
+        Episode {
+            id: id.into(),
+            title: Some(format!("Episode {}", id)),
+            ..Default::default()
+        }
+    }
+
+    fn subscription(num_episodes: usize, seen: &[&str]) -> Subscription {
+        Subscription {
+            title: "Test".into(),
+            url: "https://example.com/feed.xml".into(),
+            num_episodes,
+            seen_episodes: Some(seen.iter().map(|s| s.to_string()).collect()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_episode_id_fallbacks() {
+        let feed = r#"<rss version="2.0"><channel><title>Test</title>
+<item><title>Title</title><guid>abc</guid></item>
+<item><title>Title</title><enclosure url="https://example.com/1.mp3" length="0" type="audio/mpeg"/></item>
+<item><title>Title</title><pubDate>Mon, 01 Jan 2024 00:00:00 GMT</pubDate></item>
+</channel></rss>"#;
+        let ids: Vec<String> = Podcast::parse(feed.as_bytes())
+            .unwrap()
+            .episodes()
+            .iter()
+            .map(Episode::id)
+            .collect();
+        assert_eq!(
+            ids,
+            vec![
+                "abc",
+                "https://example.com/1.mp3",
+                "Title|Mon, 01 Jan 2024 00:00:00 GMT"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_episode_itunes_metadata() {
+        let feed = r#"<rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd">
+<channel><title>Test</title>
+<item>
+  <title>Episode</title>
+  <itunes:duration>1:02:03</itunes:duration>
+  <itunes:season>2</itunes:season>
+  <itunes:episode> 14 </itunes:episode>
+  <itunes:episodeType>Bonus</itunes:episodeType>
+  <itunes:explicit>clean</itunes:explicit>
+  <itunes:image href="https://example.com/ep.jpg"/>
+</item>
+<item><title>Bare</title></item>
+</channel></rss>"#;
+        let episodes = Podcast::parse(feed.as_bytes()).unwrap().episodes();
+        let episode = &episodes[0];
+        assert_eq!(episode.duration(), Some(Duration::from_secs(3723)));
+        assert_eq!(episode.season(), Some(2));
+        assert_eq!(episode.number(), Some(14));
+        assert_eq!(episode.episode_type(), Some(EpisodeType::Bonus));
+        assert_eq!(episode.explicit(), Some(false));
+        assert_eq!(episode.image(), Some("https://example.com/ep.jpg"));
+
+        let bare = &episodes[1];
+        assert_eq!(bare.duration(), None);
+        assert_eq!(bare.number(), None);
+        assert_eq!(bare.explicit(), None);
+    }
+
+    #[test]
+    fn test_episode_sources() {
+        let feed = r#"<rss version="2.0" xmlns:podcast="https://podcastindex.org/namespace/1.0">
+<channel><title>Test</title>
+<item>
+  <title>Episode</title>
+  <enclosure url="https://example.com/ep.mp3" length="2000" type="audio/mpeg"/>
+  <podcast:alternateEnclosure type="audio/mpeg" length="2000" bitrate="128000" default="true">
+    <podcast:source uri="https://example.com/ep.mp3"/>
+  </podcast:alternateEnclosure>
+  <podcast:alternateEnclosure type="audio/opus" bitrate="64000">
+    <podcast:source uri="ipfs://QmdwGqd3d1gXnV9j6FjtiCmtnCnRK3mgUBMiVXrrCsPF2Z"/>
+    <podcast:source uri="https://cdn.example.com/ep.opus"/>
+  </podcast:alternateEnclosure>
+  <podcast:alternateEnclosure type="audio/flac" bitrate="900000.5">
+    <podcast:source uri="https://example.com/ep.flac"></podcast:source>
+  </podcast:alternateEnclosure>
+</item>
+</channel></rss>"#;
+        let podcast = Podcast::parse(feed.as_bytes()).unwrap();
+        let episode = &podcast.episodes()[0];
+        let urls = |sources: Vec<Source>| -> Vec<String> {
+            sources.into_iter().map(|source| source.url).collect()
+        };
+        let sources = episode.sources();
+        assert_eq!(
+            urls(sources.clone()),
+            vec![
+                "https://example.com/ep.mp3",
+                "https://cdn.example.com/ep.opus",
+                "https://example.com/ep.flac"
+            ]
+        );
+        assert_eq!(sources[0].bitrate, Some(128000));
+        assert_eq!(sources[0].length, Some(2000));
+        assert_eq!(sources[1].extension(), Some("opus".into()));
+
+        let mut config = Config::default();
+        let mut sub = subscription(0, &[]);
+        assert_eq!(
+            urls(sub.sources(&config, episode))[0],
+            "https://example.com/ep.mp3"
+        );
+
+        config.preferred_types = Some(vec!["audio/flac".into(), "audio/*".into()]);
+        assert_eq!(
+            urls(sub.sources(&config, episode))[0],
+            "https://example.com/ep.flac"
+        );
+        sub.config.set("max_bitrate", Some("96")).unwrap();
+        assert_eq!(
+            urls(sub.sources(&config, episode)),
+            vec![
+                "https://cdn.example.com/ep.opus",
+                "https://example.com/ep.flac",
+                "https://example.com/ep.mp3"
+            ]
+        );
+
+        sub.config
+            .set("preferred_types", Some("audio/mpeg, audio/opus"))
+            .unwrap();
+        assert_eq!(
+            sub.config.get("preferred_types").unwrap(),
+            Some("audio/mpeg,audio/opus".into())
+        );
+        sub.config.set("max_bitrate", None).unwrap();
+        let path = sub
+            .episode_path(&config, &podcast, episode)
+            .unwrap()
+            .unwrap();
+        assert_eq!(path.extension().unwrap(), "mp3");
+    }
+
+    #[test]
+    fn test_download_path_for() {
+        let source = |url: &str, mime_type: Option<&str>| Source {
+            url: url.into(),
+            mime_type: mime_type.map(String::from),
+            length: None,
+            bitrate: None,
+        };
+        let mut download = Download {
+            id: "1".into(),
+            title: "Ep. 3".into(),
+            path: PathBuf::from("Show/Ep. 3.mp3"),
+            sources: vec![
+                source("https://example.com/3.mp3", Some("audio/mpeg")),
+                source("https://example.com/3.opus", Some("audio/opus")),
+                source("https://example.com/3.m4a", None),
+            ],
+            size: 0,
+            headers: header::HeaderMap::new(),
+            chapters: None,
+            transcript: None,
+        };
+        let paths: Vec<PathBuf> = download
+            .sources
+            .iter()
+            .map(|source| download.path_for(source))
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("Show/Ep. 3.mp3"),
+                PathBuf::from("Show/Ep. 3.opus"),
+                PathBuf::from("Show/Ep. 3.m4a")
+            ]
+        );
+
+        // Without an extension to replace, the path is kept as it is
+        download.path = PathBuf::from("Show/Ep. 3");
+        assert_eq!(
+            download.path_for(&download.sources[1]),
+            PathBuf::from("Show/Ep. 3")
+        );
+    }
+
+    #[test]
+    fn test_podcast_info() {
+        let feed = r#"<rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd" xmlns:podcast="https://podcastindex.org/namespace/1.0">
+<channel><title>Test</title><link>https://example.com/</link><description> About </description>
+<itunes:author>Host</itunes:author>
+<itunes:explicit>false</itunes:explicit>
+<itunes:image href="https://example.com/art.jpg"/>
+<itunes:category text="Technology"><itunes:category text="Podcasting"/></itunes:category>
+<category>Technology</category>
+<category>Rust</category>
+<category>rust</category>
+<podcast:locked owner="host@example.com">yes</podcast:locked>
+<podcast:funding url="https://example.com/donate">Support us</podcast:funding>
+<podcast:person role="host" href="https://example.com/host">Jane Doe</podcast:person>
+</channel></rss>"#;
+        let podcast = Podcast::parse(feed.as_bytes()).unwrap();
+        assert_eq!(podcast.description(), Some("About"));
+        assert_eq!(podcast.author(), Some("Host"));
+        assert_eq!(podcast.explicit(), Some(false));
+        assert_eq!(podcast.image(), Some("https://example.com/art.jpg"));
+        assert_eq!(
+            podcast.categories(),
+            vec!["Technology > Podcasting", "Rust"]
+        );
+        assert_eq!(podcast.locked(), Some((true, Some("host@example.com"))));
+        assert_eq!(
+            podcast.funding(),
+            vec![Funding {
+                url: "https://example.com/donate".into(),
+                text: Some("Support us".into()),
+            }]
+        );
+        assert_eq!(
+            podcast.persons(),
+            vec![Person {
+                name: "Jane Doe".into(),
+                role: Some("host".into()),
+                href: Some("https://example.com/host".into()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_paged_feed() {
+        let page = |items: &str, next: &str| {
+            Podcast::parse(
+                format!(
+                    r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom"><channel><title>Test</title>{}{}</channel></rss>"#,
+                    next, items
+                )
+                .as_bytes(),
+            )
+            .unwrap()
+        };
+        let mut first = page(
+            "<item><guid>3</guid></item><item><guid>2</guid></item>",
+            r#"<atom:link rel="next" href="page2.xml"/>"#,
+        );
+        let second = page(
+            "<item><guid>2</guid></item><item><guid>1</guid></item>",
+            r#"<atom:link rel="prev-archive" href="https://example.com/page3.xml"/>"#,
+        );
+        assert_eq!(
+            first.next_page("https://example.com/feed/page1.xml"),
+            Some("https://example.com/feed/page2.xml".into())
+        );
+        assert_eq!(
+            second.next_page("https://example.com/feed/page2.xml"),
+            Some("https://example.com/page3.xml".into())
+        );
+        assert_eq!(page("", "").next_page("https://example.com/"), None);
+
+        first.merge(&second);
+        let ids: Vec<String> = first.episodes().iter().map(Episode::id).collect();
+        assert_eq!(ids, vec!["3", "2", "1"]);
+    }
+
+    #[tokio::test]
+    async fn test_older_page_failure_keeps_fetched_pages() {
+        let dir = std::env::temp_dir().join(format!("podcast-pages-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let page = |items: &str, next: &str| {
+            format!(
+                r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom"><channel><title>Test</title><atom:link rel="next" href="{}"/>{}</channel></rss>"#,
+                next, items
+            )
+        };
+        // The third page is missing
+        fs::write(
+            dir.join("page2.xml"),
+            page("<item><guid>2</guid></item>", "page3.xml"),
+        )
+        .unwrap();
+        let first_url = Url::from_file_path(dir.join("page1.xml")).unwrap();
+        let mut podcast =
+            Podcast::parse(page("<item><guid>3</guid></item>", "page2.xml").as_bytes()).unwrap();
+        podcast
+            .fetch_older_pages(first_url.as_str(), &header::HeaderMap::new(), 0)
+            .await;
+        let ids: Vec<String> = podcast.episodes().iter().map(Episode::id).collect();
+        assert_eq!(ids, vec!["3", "2"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_new_feed_url() {
+        let feed = r#"<rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd">
+<channel><title>Test</title>
+<itunes:new-feed-url>https://example.com/new.xml</itunes:new-feed-url>
+</channel></rss>"#;
+        let podcast = Podcast::parse(feed.as_bytes()).unwrap();
+        assert_eq!(podcast.new_feed_url(), Some("https://example.com/new.xml"));
+
+        let mut sub = Subscription {
+            title: "Test".into(),
+            url: "https://example.com/feed.xml".into(),
+            ..Default::default()
+        };
+        for bad in &[
+            "new.xml",
+            "/new.xml",
+            "not a url",
+            "ftp://example.com/new.xml",
+        ] {
+            sub.move_to(bad);
+            assert_eq!(sub.url, "https://example.com/feed.xml");
         }
+        // A remote feed can't have us read local files
+        sub.move_to("file:///etc/passwd");
+        assert_eq!(sub.url, "https://example.com/feed.xml");
+        sub.move_to("http://example.org/new.xml");
+        assert_eq!(sub.url, "http://example.org/new.xml");
+
+        let mut sub = Subscription {
+            url: "file:///podcasts/feed.xml".into(),
+            ..Default::default()
+        };
+        sub.move_to("file:///podcasts/new.xml");
+        assert_eq!(sub.url, "file:///podcasts/new.xml");
     }
 
-    pub fn extension(&self) -> Option<String> {
-        match self.0.enclosure()?.mime_type() {
-            "audio/mpeg" => Some("mp3".into()),
-            "audio/mp4" => Some("m4a".into()),
-            "audio/aac" => Some("m4a".into()),
-            "audio/ogg" => Some("ogg".into()),
-            "audio/vorbis" => Some("ogg".into()),
-            "audio/opus" => Some("opus".into()),
-            _ => find_extension(self.url().unwrap()),
+    #[test]
+    fn test_find_subscription() {
+        let state = State {
+            version: "0.0.0".into(),
+            last_run_time: Utc::now(),
+            config: Config::default(),
+            subscriptions: vec![
+                Subscription {
+                    title: "First".into(),
+                    url: "https://example.com/first.xml".into(),
+                    ..Default::default()
+                },
+                Subscription {
+                    title: "Second".into(),
+                    url: "https://example.com/second.xml".into(),
+                    guid: Some("abc".into()),
+                    ..Default::default()
+                },
+            ],
+            client: utils::client(),
+        };
+        let find = |url, guid, title| state.find_subscription(&[url], guid, title);
+        assert_eq!(
+            find("http://example.com/first.xml/", None, "Other"),
+            Some(0)
+        );
+        assert_eq!(
+            find("https://example.org/feed", Some("abc"), "Renamed"),
+            Some(1)
+        );
+        assert_eq!(find("https://example.org/feed", None, "First"), Some(0));
+        assert_eq!(find("https://example.org/feed", Some("xyz"), "Other"), None);
+    }
+
+    #[test]
+    fn test_subscription_config_overrides() {
+        let config = Config::default();
+        let mut sub = subscription(0, &[]);
+        assert_eq!(sub.download_subscription_limit(&config), Some(1));
+
+        sub.config
+            .set("download_subscription_limit", Some("5"))
+            .unwrap();
+        assert_eq!(sub.download_subscription_limit(&config), Some(5));
+        assert_eq!(
+            sub.config.get("download_subscription_limit").unwrap(),
+            Some("5".into())
+        );
+
+        sub.config.set("never_auto_download", Some("true")).unwrap();
+        assert_eq!(sub.download_subscription_limit(&config), Some(0));
+        assert_eq!(sub.auto_download_limit(&config), 0);
+
+        sub.config.set("never_auto_download", None).unwrap();
+        assert_eq!(sub.auto_download_limit(&config), 1);
+        assert!(sub.config.set("playback_speed", Some("fast")).is_err());
+        assert!(sub.config.set("unknown", Some("1")).is_err());
+    }
+
+    #[test]
+    fn test_rename_rejects_bad_names() {
+        let mut sub = subscription(0, &[]);
+        for name in &["", "  ", ".rss", "a/b"] {
+            assert!(sub.rename(name).is_err());
         }
+        assert_eq!(sub.title(), "Test");
+    }
+
+    #[test]
+    fn test_new_episodes_by_id() {
+        // A publisher deleting an old episode shouldn't hide the new one
+        let episodes = vec![episode("4"), episode("3"), episode("1")];
+        let sub = subscription(3, &["1", "2", "3"]);
+        assert_eq!(sub.new_episodes(&episodes), vec![episode("4")]);
+    }
+
+    #[test]
+    fn test_new_episodes_migrates_count() {
+        let episodes = vec![episode("3"), episode("2"), episode("1")];
+        let mut sub = subscription(2, &[]);
+        sub.seen_episodes = None;
+        assert_eq!(sub.new_episodes(&episodes), vec![episode("3")]);
+
+        sub.mark_seen(&episodes);
+        assert!(sub.new_episodes(&episodes).is_empty());
+        assert_eq!(sub.seen_episodes.as_ref().map(BTreeSet::len), Some(3));
+    }
+
+    #[test]
+    fn test_new_episodes_retries_only_failed_episode() {
+        // The only episode of a new subscription failed to download, leaving nothing seen
+        let episodes = vec![episode("1")];
+        let mut sub = subscription(0, &[]);
+        sub.mark_seen(&episodes);
+        sub.set_seen("1", false);
+        assert_eq!(sub.new_episodes(&episodes), vec![episode("1")]);
+
+        // Still the case once the state has been saved and loaded again
+        let sub: Subscription =
+            serde_json::from_str(&serde_json::to_string(&sub).unwrap()).unwrap();
+        assert_eq!(sub.new_episodes(&episodes), vec![episode("1")]);
     }
 }
@@ -0,0 +1,256 @@
+use crate::utils;
+use anyhow::{anyhow, Result};
+use regex::Regex;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+lazy_static! {
+    static ref TAG: Regex = Regex::new(r"<[^>]*>").unwrap();
+    static ref BLOCK_END: Regex = Regex::new(r"(?i)</?(p|div|br|li|h\d|tr)\b[^>]*>").unwrap();
+}
+
+/// The transcript formats we can read, in order of preference
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Format {
+    Srt,
+    Vtt,
+    Json,
+    Html,
+}
+
+impl Format {
+    pub const ALL: [Format; 4] = [Format::Srt, Format::Vtt, Format::Json, Format::Html];
+
+    pub fn from_mime_type(mime_type: &str) -> Option<Format> {
+        match mime_type.trim().to_lowercase().as_str() {
+            "application/x-subrip" | "application/srt" | "text/srt" => Some(Format::Srt),
+            "text/vtt" => Some(Format::Vtt),
+            "application/json" => Some(Format::Json),
+            "text/html" => Some(Format::Html),
+            _ => None,
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Format::Srt => "application/x-subrip",
+            Format::Vtt => "text/vtt",
+            Format::Json => "application/json",
+            Format::Html => "text/html",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Srt => "srt",
+            Format::Vtt => "vtt",
+            Format::Json => "json",
+            Format::Html => "html",
+        }
+    }
+}
+
+/// A transcript linked from an episode with `<podcast:transcript>`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Link {
+    pub url: String,
+    pub format: Format,
+}
+
+/// A piece of the transcript, with when it starts if the format says so
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cue {
+    pub start: Option<Duration>,
+    pub text: String,
+}
+
+impl Cue {
+    pub fn line(&self) -> String {
+        match self.start {
+            Some(start) => format!("[{}] {}", utils::format_duration(start), self.text),
+            None => self.text.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonTranscript {
+    segments: Vec<JsonSegment>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonSegment {
+    speaker: Option<String>,
+    start_time: Option<f64>,
+    body: String,
+}
+
+pub fn parse(format: Format, content: &str) -> Result<Vec<Cue>> {
+    match format {
+        Format::Srt | Format::Vtt => Ok(parse_subtitles(content)),
+        Format::Json => {
+            let transcript: JsonTranscript = serde_json::from_str(content)?;
+            Ok(transcript
+                .segments
+                .into_iter()
+                .map(|segment| Cue {
+                    start: segment
+                        .start_time
+                        .and_then(|secs| utils::duration_from_secs(secs.max(0.0))),
+                    text: match segment.speaker {
+                        Some(speaker) => format!("{}: {}", speaker, segment.body.trim()),
+                        None => segment.body.trim().to_string(),
+                    },
+                })
+                .collect())
+        }
+        Format::Html => {
+            let text = BLOCK_END.replace_all(content, "\n");
+            Ok(TAG
+                .replace_all(&text, "")
+                .lines()
+                .map(|line| unescape(line.trim()))
+                .filter(|line| !line.is_empty())
+                .map(|text| Cue { start: None, text })
+                .collect())
+        }
+    }
+}
+
+/// Reads SRT and WebVTT, which both consist of cues with a `start --> end` timing line
+fn parse_subtitles(content: &str) -> Vec<Cue> {
+    let mut cues = vec![];
+    for block in content.replace("\r\n", "\n").split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        let start = match lines.next() {
+            Some(timing) => parse_timestamp(timing.split("-->").next().unwrap_or_default()),
+            None => continue,
+        };
+        let text: Vec<String> = lines
+            .map(|line| unescape(TAG.replace_all(line, "").trim()))
+            .filter(|line| !line.is_empty())
+            .collect();
+        if !text.is_empty() {
+            cues.push(Cue {
+                start,
+                text: text.join(" "),
+            });
+        }
+    }
+    cues
+}
+
+/// Parses `HH:MM:SS,mmm` (SRT) and `HH:MM:SS.mmm` or `MM:SS.mmm` (WebVTT)
+fn parse_timestamp(timestamp: &str) -> Option<Duration> {
+    utils::parse_duration(&timestamp.trim().replace(',', "."))
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+/// Where a transcript in the given format is kept for the episode saved at the given path.
+/// The suffix is added to the whole filename, since a title like "Ep. 3" has no extension.
+pub fn path(episode_path: &Path, format: Format) -> PathBuf {
+    utils::append_to_file_name(episode_path, &format!(".transcript.{}", format.extension()))
+}
+
+/// Finds a transcript saved next to the episode at the given path
+pub fn find(episode_path: &Path) -> Option<(PathBuf, Format)> {
+    Format::ALL
+        .iter()
+        .map(|format| (path(episode_path, *format), *format))
+        .find(|(path, _)| path.exists())
+}
+
+/// Reads a transcript saved by `podcast download`
+pub fn read(path: &Path) -> Result<Vec<Cue>> {
+    let format = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(|ext| Format::ALL.iter().find(|f| f.extension() == ext))
+        .ok_or_else(|| anyhow!("{} isn't a transcript", path.display()))?;
+    parse(*format, &fs::read_to_string(path)?)
+}
+
+/// Splits a saved transcript's filename into the episode's filename, if it is one
+pub fn episode_name(transcript_path: &Path) -> Option<String> {
+    let name = transcript_path.file_name()?.to_str()?;
+    Format::ALL.iter().find_map(|format| {
+        name.strip_suffix(&format!(".transcript.{}", format.extension()))
+            .map(String::from)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_srt() {
+        let srt = "1\r\n00:00:01,500 --> 00:00:04,000\r\nHello &amp; welcome\r\nto the show\r\n\r\n2\r\n01:02:03,000 --> 01:02:05,000\r\n<i>Bye</i>\r\n";
+        assert_eq!(
+            parse(Format::Srt, srt).unwrap(),
+            vec![
+                Cue {
+                    start: Some(Duration::from_millis(1500)),
+                    text: "Hello & welcome to the show".into()
+                },
+                Cue {
+                    start: Some(Duration::from_secs(3723)),
+                    text: "Bye".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_vtt() {
+        let vtt = "WEBVTT\n\nNOTE a comment\n\nintro\n00:05.000 --> 00:07.000 align:start\n<v Alice>Hi there\n";
+        let cues = parse(Format::Vtt, vtt).unwrap();
+        assert_eq!(cues.len(), 1);
+        assert_eq!(cues[0].line(), "[0:05] Hi there");
+    }
+
+    #[test]
+    fn test_parse_json_and_html() {
+        let json = r#"{"version": "1.0.0", "segments": [{"speaker": "Alice", "startTime": 61.2, "endTime": 63, "body": "Hi"}]}"#;
+        assert_eq!(
+            parse(Format::Json, json).unwrap()[0].line(),
+            "[1:01] Alice: Hi"
+        );
+        let json = r#"{"version": "1.0.0", "segments": [{"startTime": 1e300, "body": "Late"}]}"#;
+        assert_eq!(parse(Format::Json, json).unwrap()[0].line(), "Late");
+
+        let html = "<html><body><p><cite>Alice:</cite> Hi</p>\n<p>Q&amp;A</p></body></html>";
+        let lines: Vec<String> = parse(Format::Html, html)
+            .unwrap()
+            .iter()
+            .map(Cue::line)
+            .collect();
+        assert_eq!(lines, vec!["Alice: Hi", "Q&A"]);
+    }
+
+    #[test]
+    fn test_transcript_paths() {
+        let saved = path(Path::new("/podcasts/Show/1-Episode.mp3"), Format::Vtt);
+        assert_eq!(
+            saved,
+            Path::new("/podcasts/Show/1-Episode.mp3.transcript.vtt")
+        );
+        assert_eq!(episode_name(&saved), Some("1-Episode.mp3".into()));
+        assert_eq!(episode_name(Path::new("1-Episode.mp3")), None);
+
+        let saved = path(Path::new("/podcasts/Show/Ep. 3"), Format::Srt);
+        assert_eq!(saved, Path::new("/podcasts/Show/Ep. 3.transcript.srt"));
+        assert_eq!(episode_name(&saved), Some("Ep. 3".into()));
+    }
+}
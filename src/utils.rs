@@ -1,14 +1,350 @@
 use std::collections::HashSet;
 use std::env;
-use std::fs::{self, DirBuilder, File};
-use std::io::{BufReader, Write};
-use std::path::PathBuf;
+use std::fmt;
+use std::fs::{self, DirBuilder};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
 
-use rss::Channel;
+use reqwest::{header, redirect, Method, StatusCode, Url};
 
 const UNSUBSCRIBE_NOTE: &str = "Note: this does NOT delete any downloaded podcasts";
+const MAX_REDIRECTS: usize = 10;
+/// How many times a failed request is retried, unless configured otherwise
+pub(crate) const RETRIES: u32 = 3;
+/// How long to wait before the first retry, doubling for every retry after it
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+/// The longest we wait between retries, including when a server asks for longer
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+lazy_static! {
+    // Redirects are followed by `send_following`, which knows to leave credentials behind
+    static ref CLIENT: reqwest::Client = reqwest::Client::builder()
+        .redirect(redirect::Policy::none())
+        .build()
+        .unwrap();
+}
+
+/// The client for every request that may carry credentials. It doesn't follow redirects
+/// itself, so requests made with it should go through `send` or `send_following`.
+pub fn client() -> reqwest::Client {
+    CLIENT.clone()
+}
+
+/// A feed response along with where it came from after redirects, and where the feed
+/// has permanently moved, if anywhere
+pub struct FeedResponse {
+    pub response: reqwest::Response,
+    pub url: String,
+    pub moved_to: Option<String>,
+}
+
+/// The local file a `file://` URL points to
+pub fn file_path(url: &str) -> Option<PathBuf> {
+    Url::parse(url)
+        .ok()
+        .filter(|url| url.scheme() == "file")
+        .and_then(|url| url.to_file_path().ok())
+}
+
+/// Whether a feed may link to the given URL. Only a local feed may link to local files,
+/// since a feed served over HTTP could otherwise have us copy or print any file we can read.
+pub fn may_link_to(feed_url: &str, url: &str) -> bool {
+    file_path(url).is_none() || file_path(feed_url).is_some()
+}
+
+/// Whether a feed may send us to the given URL as its new home. It has to be an absolute
+/// http(s) URL, unless a local feed is moving to another local file.
+pub fn may_move_to(feed_url: &str, new_url: &str) -> bool {
+    match Url::parse(new_url) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => url.has_host(),
+        Ok(url) if url.scheme() == "file" => file_path(feed_url).is_some(),
+        _ => false,
+    }
+}
+
+/// Turns a path to a local feed into a `file://` URL, leaving anything else as it is
+pub fn feed_url(input: &str) -> Result<String> {
+    let path = Path::new(input);
+    if Url::parse(input).is_err() || path.exists() {
+        let path = path
+            .canonicalize()
+            .map_err(|err| anyhow!("Couldn't open {}: {}", input, err))?;
+        return Url::from_file_path(&path)
+            .map(String::from)
+            .map_err(|_| anyhow!("Couldn't open {}", input));
+    }
+    Ok(input.to_string())
+}
+
+/// Fetches the content of a URL, which may also be a `file://` URL
+pub async fn fetch(
+    client: &reqwest::Client,
+    url: &str,
+    headers: header::HeaderMap,
+) -> Result<Vec<u8>> {
+    if let Some(path) = file_path(url) {
+        return Ok(tokio::fs::read(path).await?);
+    }
+    Ok(send(client, Method::GET, url, headers)
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?
+        .to_vec())
+}
+
+/// An unsuccessful HTTP response, with what we need to know to decide whether to retry it
+#[derive(Debug)]
+pub struct StatusError {
+    pub url: String,
+    pub status: StatusCode,
+    /// How long the server asked us to wait, from `Retry-After`
+    pub retry_after: Option<Duration>,
+}
+
+impl From<&reqwest::Response> for StatusError {
+    fn from(response: &reqwest::Response) -> Self {
+        let retry_after = response
+            .headers()
+            .get(header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+        StatusError {
+            url: response.url().to_string(),
+            status: response.status(),
+            retry_after,
+        }
+    }
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HTTP status {} for url ({})", self.status, self.url)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// Like `Response::error_for_status`, but keeps what's needed to retry
+pub fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    if response.status().is_client_error() || response.status().is_server_error() {
+        return Err(StatusError::from(&response).into());
+    }
+    Ok(response)
+}
+
+/// Server errors and rate limiting may well go away if we wait
+fn is_retryable(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// `Retry-After` is either a number of seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        date.signed_duration_since(Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// How long to wait before retrying after the given error, or None if retrying won't help.
+/// Connection problems, timeouts and server errors are retried with exponential backoff,
+/// and a `429` or `503` waits as long as its `Retry-After` asks.
+fn retry_delay(err: &anyhow::Error, retry: u32) -> Option<Duration> {
+    if let Some(err) = err.downcast_ref::<StatusError>() {
+        if !is_retryable(err.status) {
+            return None;
+        }
+        let honored = [
+            StatusCode::TOO_MANY_REQUESTS,
+            StatusCode::SERVICE_UNAVAILABLE,
+        ];
+        if let Some(after) = err.retry_after.filter(|_| honored.contains(&err.status)) {
+            return Some(after.min(MAX_RETRY_DELAY));
+        }
+    } else if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+        if let Some(status) = err.status() {
+            if !is_retryable(status) {
+                return None;
+            }
+        } else if !(err.is_timeout() || err.is_connect() || err.is_request() || err.is_body()) {
+            return None;
+        }
+    } else {
+        return None;
+    }
+
+    let delay = RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(retry))
+        .min(MAX_RETRY_DELAY);
+    // Jitter keeps many downloads that failed together from retrying together.
+    // The clock's nanoseconds are random enough for that.
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    Some(delay + delay.mul_f64(f64::from(nanos % 1000) / 2000.0))
+}
+
+/// Runs the given request, retrying it up to `retries` times if it fails in a way
+/// that might not last
+pub async fn with_retries<T, F, Fut>(retries: u32, mut request: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut retry = 0;
+    loop {
+        match request().await {
+            Ok(result) => return Ok(result),
+            Err(err) => match retry_delay(&err, retry).filter(|_| retry < retries) {
+                Some(delay) => {
+                    tokio::time::sleep(delay).await;
+                    retry += 1;
+                }
+                None => return Err(err),
+            },
+        }
+    }
+}
+
+/// Sends a request, following redirects ourselves
+pub async fn send(
+    client: &reqwest::Client,
+    method: Method,
+    url: &str,
+    headers: header::HeaderMap,
+) -> Result<reqwest::Response> {
+    send_following(client, method, url, headers, |_, _| ()).await
+}
+
+/// Sends a request, following redirects ourselves and telling `redirected` the status of
+/// each redirect and where it leads. Like browsers, we don't hand credentials to whichever
+/// host we're redirected to: once the scheme, host or port changes, the headers marked
+/// sensitive, which includes every header made by `auth::headers`, are no longer sent.
+pub async fn send_following<F>(
+    client: &reqwest::Client,
+    method: Method,
+    url: &str,
+    mut headers: header::HeaderMap,
+    mut redirected: F,
+) -> Result<reqwest::Response>
+where
+    F: FnMut(StatusCode, &Url),
+{
+    let mut url = Url::parse(url)?;
+    for _ in 0..MAX_REDIRECTS {
+        let response = client
+            .request(method.clone(), url.clone())
+            .headers(headers.clone())
+            .send()
+            .await?;
+        let status = response.status();
+        if !is_redirect(status) {
+            return Ok(response);
+        }
+
+        let location = response
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .ok_or_else(|| anyhow!("{} redirected without a location", url))?;
+        let next = url.join(location)?;
+        if !same_origin(&next, &url) {
+            strip_sensitive(&mut headers);
+        }
+        url = next;
+        redirected(status, &url);
+    }
+    Err(anyhow!("Too many redirects fetching {}", url))
+}
+
+fn is_redirect(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::MOVED_PERMANENTLY
+            | StatusCode::FOUND
+            | StatusCode::SEE_OTHER
+            | StatusCode::TEMPORARY_REDIRECT
+            | StatusCode::PERMANENT_REDIRECT
+    )
+}
+
+/// Whether two URLs have the same scheme, host and port, so that a redirect from one to
+/// the other may keep the credentials
+fn same_origin(a: &Url, b: &Url) -> bool {
+    a.scheme() == b.scheme()
+        && a.host_str() == b.host_str()
+        && a.port_or_known_default() == b.port_or_known_default()
+}
+
+fn strip_sensitive(headers: &mut header::HeaderMap) {
+    let sensitive: Vec<header::HeaderName> = headers
+        .iter()
+        .filter(|(_, value)| value.is_sensitive())
+        .map(|(name, _)| name.clone())
+        .collect();
+    for name in sensitive {
+        headers.remove(name);
+    }
+}
+
+/// Fetches a feed, following redirects ourselves.
+/// The feed only counts as moved if every redirect on the way was permanent (301/308).
+/// Local feeds given as `file://` URLs are read as if they were served with a 200.
+pub async fn fetch_feed(
+    url: &str,
+    headers: header::HeaderMap,
+    retries: u32,
+) -> Result<FeedResponse> {
+    if let Some(path) = file_path(url) {
+        let content = tokio::fs::read(&path)
+            .await
+            .map_err(|err| anyhow!("Couldn't read {}: {}", path.display(), err))?;
+        return Ok(FeedResponse {
+            response: http::Response::new(content).into(),
+            url: url.to_string(),
+            moved_to: None,
+        });
+    }
+
+    let (response, moved_to) = with_retries(retries, || {
+        let headers = headers.clone();
+        async move {
+            let mut permanent = true;
+            let mut moved_to = None;
+            let response = send_following(&CLIENT, Method::GET, url, headers, |status, next| {
+                permanent &= matches!(
+                    status,
+                    StatusCode::MOVED_PERMANENTLY | StatusCode::PERMANENT_REDIRECT
+                );
+                if permanent {
+                    moved_to = Some(next.to_string());
+                }
+            })
+            .await?;
+            if is_retryable(response.status()) {
+                return Err(StatusError::from(&response).into());
+            }
+            Ok((response, moved_to))
+        }
+    })
+    .await?;
+    Ok(FeedResponse {
+        url: response.url().to_string(),
+        response,
+        moved_to,
+    })
+}
 
 pub fn trim_extension(filename: &str) -> Option<String> {
     let name = String::from(filename);
@@ -30,6 +366,77 @@ pub fn find_extension(input: &str) -> Option<String> {
     None
 }
 
+/// Reduces a feed URL to a form where trivially different spellings of it compare equal
+pub fn normalize_url(url: &str) -> String {
+    match Url::parse(url.trim()) {
+        Ok(parsed) => format!(
+            "{}{}{}{}",
+            parsed.host_str().unwrap_or("").trim_start_matches("www."),
+            parsed.port().map(|p| format!(":{}", p)).unwrap_or_default(),
+            parsed.path().trim_end_matches('/'),
+            parsed
+                .query()
+                .map(|q| format!("?{}", q))
+                .unwrap_or_default()
+        ),
+        Err(_) => url.trim().to_string(),
+    }
+}
+
+/// Parses a duration in the `HH:MM:SS`, `MM:SS` or plain seconds forms used by `<itunes:duration>`
+pub fn parse_duration(input: &str) -> Option<Duration> {
+    let parts: Vec<&str> = input.trim().split(':').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+    let (seconds, units) = parts.split_last()?;
+    let mut total: u64 = 0;
+    for unit in units {
+        total = total
+            .checked_mul(60)?
+            .checked_add(unit.trim().parse().ok()?)?;
+    }
+    let seconds = seconds.trim().parse::<f64>().ok().filter(|s| *s >= 0.0)?;
+    Duration::from_secs(total.checked_mul(60)?).checked_add(duration_from_secs(seconds)?)
+}
+
+/// Like `Duration::from_secs_f64`, but None instead of a panic for a number of seconds
+/// that's negative, not finite or too large, as feeds may well give us
+pub fn duration_from_secs(secs: f64) -> Option<Duration> {
+    Some(secs)
+        .filter(|secs| secs.is_finite())
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+}
+
+/// Parses a rate in bytes per second, optionally with a `K`, `M` or `G` suffix for
+/// multiples of 1024 like curl's `--limit-rate`
+pub fn parse_rate(input: &str) -> Result<u64> {
+    let input = input.trim();
+    let (number, multiplier) = match input.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&input[..input.len() - 1], 1024.0),
+        Some('M') => (&input[..input.len() - 1], 1024.0 * 1024.0),
+        Some('G') => (&input[..input.len() - 1], 1024.0 * 1024.0 * 1024.0),
+        _ => (input, 1.0),
+    };
+    let rate = number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .map(|number| (number * multiplier).round())
+        .filter(|rate| 1.0 <= *rate)
+        .ok_or_else(|| anyhow!("Invalid download rate: {}", input))?;
+    Ok(rate as u64)
+}
+
+/// Formats a duration as `H:MM:SS`, or `M:SS` if it's under an hour
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs / 3600 {
+        0 => format!("{}:{:02}", secs / 60, secs % 60),
+        hours => format!("{}:{:02}:{:02}", hours, secs / 60 % 60, secs % 60),
+    }
+}
+
 pub fn get_podcast_dir() -> Result<PathBuf> {
     match env::var_os("PODCAST") {
         Some(val) => Ok(PathBuf::from(val)),
@@ -50,8 +457,15 @@ pub fn append_extension(filename: &str, ext: &str) -> String {
     f
 }
 
+/// Adds a suffix to the last component of a path, whether or not it already has an extension
+pub fn append_to_file_name(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
 pub fn create_dir_if_not_exist(path: &PathBuf) -> Result<()> {
-    DirBuilder::new().recursive(true).create(&path)?;
+    DirBuilder::new().recursive(true).create(path)?;
     Ok(())
 }
 
@@ -79,13 +493,10 @@ pub fn delete_all() -> Result<()> {
     Ok(())
 }
 
-pub fn already_downloaded(dir: &str) -> Result<HashSet<String>> {
+pub fn already_downloaded(dir: &Path) -> Result<HashSet<String>> {
     let mut result = HashSet::new();
 
-    let mut path = get_podcast_dir()?;
-    path.push(dir);
-
-    let entries = fs::read_dir(path)?;
+    let entries = fs::read_dir(dir)?;
     for entry in entries {
         let entry = entry?;
         match entry.file_name().into_string() {
@@ -123,21 +534,6 @@ pub fn get_xml_dir() -> Result<PathBuf> {
     Ok(path)
 }
 
-pub async fn download_rss_feed(url: &str) -> Result<Channel> {
-    println!("Downloading RSS feed...");
-    let mut path = get_podcast_dir()?;
-    path.push(".rss");
-    create_dir_if_not_exist(&path)?;
-    let resp = reqwest::get(url).await?.bytes().await?;
-    let channel = Channel::read_from(BufReader::new(&resp[..]))?;
-    let mut filename = String::from(channel.title());
-    filename.push_str(".xml");
-    path.push(filename);
-    let mut file = File::create(&path)?;
-    file.write_all(&resp)?;
-    Ok(channel)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -177,6 +573,163 @@ mod tests {
         assert_eq!(find_extension("test"), None)
     }
 
+    #[test]
+    fn test_normalize_url() {
+        assert_eq!(
+            normalize_url("HTTP://www.Example.com:80/feed/?a=1#top"),
+            normalize_url("https://example.com/feed?a=1")
+        );
+        assert_ne!(
+            normalize_url("https://example.com/feed?a=1"),
+            normalize_url("https://example.com/feed?a=2")
+        );
+    }
+
+    #[test]
+    fn test_local_feed_urls() {
+        let dir = env::temp_dir().canonicalize().unwrap();
+        let url = feed_url(dir.to_str().unwrap()).unwrap();
+        assert!(url.starts_with("file://"));
+        assert_eq!(file_path(&url), Some(dir));
+        assert_eq!(
+            feed_url("https://example.com/feed.xml").unwrap(),
+            "https://example.com/feed.xml"
+        );
+        assert!(feed_url("no/such/feed.xml").is_err());
+        assert_eq!(file_path("https://example.com/feed.xml"), None);
+
+        let remote = "https://example.com/feed.xml";
+        assert!(may_link_to(&url, "file:///tmp/episode.mp3"));
+        assert!(may_link_to(&url, "https://example.com/episode.mp3"));
+        assert!(may_link_to(remote, "https://example.com/episode.mp3"));
+        assert!(!may_link_to(remote, "file:///etc/passwd"));
+    }
+
+    #[test]
+    fn test_strip_sensitive() {
+        let mut headers = header::HeaderMap::new();
+        let mut key = header::HeaderValue::from_static("secret");
+        key.set_sensitive(true);
+        headers.insert("x-api-key", key);
+        headers.insert(header::RANGE, "bytes=100-".parse().unwrap());
+        strip_sensitive(&mut headers);
+        assert!(!headers.contains_key("x-api-key"));
+        assert_eq!(headers[header::RANGE], "bytes=100-");
+
+        let url = |url: &str| Url::parse(url).unwrap();
+        let feed = url("https://example.com/feed.xml");
+        assert!(same_origin(
+            &feed,
+            &url("https://example.com:443/other.xml")
+        ));
+        // Downgrading to http would send the credentials in the clear
+        assert!(!same_origin(&feed, &url("http://example.com/feed.xml")));
+        assert!(!same_origin(
+            &feed,
+            &url("https://example.com:8443/feed.xml")
+        ));
+        assert!(!same_origin(
+            &feed,
+            &url("https://cdn.example.com/feed.xml")
+        ));
+    }
+
+    fn status_error(status: u16, retry_after: Option<u64>) -> anyhow::Error {
+        StatusError {
+            url: "https://example.com/feed.xml".into(),
+            status: StatusCode::from_u16(status).unwrap(),
+            retry_after: retry_after.map(Duration::from_secs),
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(&status_error(404, None), 0), None);
+        assert!(retry_delay(&status_error(500, Some(10)), 0).unwrap() <= RETRY_DELAY * 3 / 2);
+        assert!(retry_delay(&status_error(502, None), 2).unwrap() >= RETRY_DELAY * 4);
+        assert!(retry_delay(&status_error(502, None), 2).unwrap() <= RETRY_DELAY * 6);
+        assert_eq!(
+            retry_delay(&status_error(429, Some(7)), 0),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(
+            retry_delay(&status_error(503, Some(100_000)), 0),
+            Some(MAX_RETRY_DELAY)
+        );
+        assert_eq!(retry_delay(&anyhow!("Not a network error"), 0), None);
+
+        assert_eq!(parse_retry_after(" 120 "), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[tokio::test]
+    async fn test_with_retries() {
+        let mut attempts = 0;
+        let result: Result<()> = with_retries(2, || {
+            attempts += 1;
+            async { Err(status_error(503, Some(0))) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+
+        let mut attempts = 0;
+        let result = with_retries(2, || {
+            attempts += 1;
+            let attempt = attempts;
+            async move {
+                match attempt {
+                    1 => Err(status_error(429, Some(0))),
+                    _ => Ok(attempt),
+                }
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 2);
+
+        let mut attempts = 0;
+        let result: Result<()> = with_retries(2, || {
+            attempts += 1;
+            async { Err(status_error(404, None)) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("2M").unwrap(), 2 * 1024 * 1024);
+        assert_eq!(parse_rate("1.5k").unwrap(), 1536);
+        assert_eq!(parse_rate(" 1000 ").unwrap(), 1000);
+        assert!(parse_rate("fast").is_err());
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("-1M").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("1:02:03"), Some(Duration::from_secs(3723)));
+        assert_eq!(parse_duration("62:03"), Some(Duration::from_secs(3723)));
+        assert_eq!(parse_duration(" 3723 "), Some(Duration::from_secs(3723)));
+        assert_eq!(parse_duration("90.5"), Some(Duration::from_millis(90500)));
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("1:2:3:4"), None);
+        assert_eq!(parse_duration("an hour"), None);
+        assert_eq!(parse_duration("inf"), None);
+        assert_eq!(parse_duration("1e400"), None);
+        assert_eq!(parse_duration("NaN"), None);
+        assert_eq!(parse_duration("99999999999999999999:00"), None);
+        assert_eq!(parse_duration("307445734561825860:00:00"), None);
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1:02:03");
+        assert_eq!(format_duration(Duration::from_secs(63)), "1:03");
+    }
+
     #[test]
     fn test_trim_extension() {
         assert_eq!(trim_extension("test.taco"), Some(String::from("test")))
@@ -0,0 +1,192 @@
+use anyhow::{anyhow, Result};
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Checks that a finished download has every size we were told to expect, so one that was
+/// cut short isn't kept. Each size is given with where it came from, for the error.
+pub fn check_size(path: &Path, expected: &[(Option<u64>, &str)]) -> Result<()> {
+    let saved = path.metadata()?.len();
+    for (size, source) in expected {
+        if let Some(size) = size.filter(|size| *size != saved) {
+            return Err(anyhow!(
+                "Expected {} bytes going by {} but got {}",
+                size,
+                source,
+                saved
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that a finished download is the media the feed promised, rather than an error
+/// page or some other file served in its place
+pub fn check(path: &Path, expected_type: Option<&str>, content_type: Option<&str>) -> Result<()> {
+    let expected_type = expected_type.map(essence).unwrap_or_default();
+    let expected_kind = top_level(&expected_type);
+    // Episodes are meant to be audio or video, but some feeds leave out or misstate the type
+    if !(expected_type.is_empty() || matches!(expected_kind, "audio" | "video")) {
+        return Ok(());
+    }
+    let expected_type = Some(expected_type.as_str())
+        .filter(|t| !t.is_empty())
+        .unwrap_or("media");
+
+    let mut start = vec![0; 512];
+    let read = File::open(path)?.read(&mut start)?;
+    let start = &start[..read];
+    let kinds = sniff(start);
+
+    let content_type = content_type.map(essence);
+    if let Some(content_type) = &content_type {
+        let kind = top_level(content_type);
+        // Many servers send MP4 audio as video/mp4, so the file's own bytes win over the
+        // server about which kind of media it is
+        let confirmed = kinds.is_some_and(|kinds| kinds.contains(&expected_kind));
+        let wrong_media = matches!(kind, "audio" | "video") && kind != expected_kind;
+        if is_document(content_type) || (wrong_media && !expected_kind.is_empty() && !confirmed) {
+            return Err(anyhow!(
+                "Expected {} but the server sent {}",
+                expected_type,
+                content_type
+            ));
+        }
+    }
+
+    if looks_like_document(start) {
+        return Err(anyhow!(
+            "Expected {} but got what looks like a web page",
+            expected_type
+        ));
+    }
+    if expected_kind.is_empty() {
+        return Ok(());
+    }
+    match kinds {
+        Some(kinds) if !kinds.contains(&expected_kind) => Err(anyhow!(
+            "Expected {} but got what looks like {}",
+            expected_type,
+            kinds.join(" or ")
+        )),
+        Some(_) => Ok(()),
+        // A format we don't know is fine as long as the server says it's the right kind
+        None if content_type.as_deref().map(top_level) == Some(expected_kind) => Ok(()),
+        None => Err(anyhow!(
+            "Expected {} but got a file in a format we don't recognize",
+            expected_type
+        )),
+    }
+}
+
+/// The MIME type without its parameters, like `text/html` from `text/html; charset=utf-8`
+fn essence(mime_type: &str) -> String {
+    mime_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase()
+}
+
+/// The type of a MIME type without its subtype, like `audio` from `audio/mpeg`
+fn top_level(mime_type: &str) -> &str {
+    mime_type.split('/').next().unwrap_or_default()
+}
+
+fn is_document(mime_type: &str) -> bool {
+    mime_type.starts_with("text/")
+        || matches!(
+            mime_type,
+            "application/json" | "application/xml" | "application/xhtml+xml"
+        )
+}
+
+/// Whether a file that starts with the given bytes is audio, video or could be either,
+/// going by the magic bytes of MP3, AAC, MP4, Ogg, FLAC, WAV and WebM, or None if it's in
+/// none of those formats
+fn sniff(start: &[u8]) -> Option<&'static [&'static str]> {
+    const AUDIO: &[&str] = &["audio"];
+    const EITHER: &[&str] = &["audio", "video"];
+    match start {
+        [b'I', b'D', b'3', ..] => Some(AUDIO),
+        // An MPEG audio or ADTS frame, which is how MP3s without tags and raw AAC start
+        [0xFF, second, ..] if second & 0xE0 == 0xE0 => Some(AUDIO),
+        [_, _, _, _, b'f', b't', b'y', b'p', brand @ ..] => match brand.get(..3) {
+            Some(b"M4A") | Some(b"M4B") | Some(b"M4P") => Some(AUDIO),
+            _ => Some(EITHER),
+        },
+        [b'O', b'g', b'g', b'S', ..] => Some(EITHER),
+        [b'f', b'L', b'a', b'C', ..] => Some(AUDIO),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Some(AUDIO),
+        // EBML, which WebM and Matroska files start with
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => Some(EITHER),
+        _ => None,
+    }
+}
+
+/// Whether the file starts like HTML, XML or JSON, which no audio or video format does
+fn looks_like_document(start: &[u8]) -> bool {
+    let start = start.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(start);
+    matches!(
+        start.iter().find(|b| !b.is_ascii_whitespace()),
+        Some(b'<') | Some(b'{')
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_check_downloads() {
+        let dir = std::env::temp_dir().join(format!("podcast-verify-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("1-Episode.mp3");
+
+        fs::write(&path, b"ID3\x04\x00\x00\x00\x00\x00\x00audio").unwrap();
+        assert!(check(&path, Some("audio/mpeg"), Some("audio/mpeg")).is_ok());
+        assert!(check(&path, None, Some("application/octet-stream")).is_ok());
+        assert!(check_size(&path, &[(Some(15), "the server"), (None, "the feed")]).is_ok());
+        assert!(check_size(&path, &[(Some(15), "the server"), (Some(2000), "the feed")]).is_err());
+        assert!(check(&path, Some("audio/mpeg"), Some("text/html; charset=utf-8")).is_err());
+
+        fs::write(
+            &path,
+            b"\n  <!DOCTYPE html><html><body>Not found</body></html>",
+        )
+        .unwrap();
+        assert!(check(&path, Some("audio/mpeg"), None).is_err());
+        assert!(check(&path, Some("application/pdf"), None).is_ok());
+
+        fs::write(&path, b"\x00\x00\x00\x18ftypisom\x00\x00\x02\x00").unwrap();
+        assert!(check(&path, Some("video/mp4"), Some("video/mp4")).is_ok());
+        // The server calling it video doesn't matter when the file may well be audio
+        assert!(check(&path, Some("audio/mpeg"), Some("video/mp4")).is_ok());
+        fs::write(&path, b"\x00\x00\x00\x18ftypM4A \x00\x00\x02\x00").unwrap();
+        assert!(check(&path, Some("audio/x-m4a"), None).is_ok());
+        assert!(check(&path, Some("video/mp4"), None).is_err());
+        assert!(check(&path, Some("video/mp4"), Some("audio/mp4")).is_err());
+
+        fs::write(&path, b"\x00\x01\x02\x03 not media").unwrap();
+        assert!(check(&path, Some("audio/mpeg"), None).is_err());
+        assert!(check(&path, Some("audio/mpeg"), Some("application/octet-stream")).is_err());
+        assert!(check(&path, Some("audio/aiff"), Some("audio/aiff")).is_ok());
+        assert!(check(&path, None, None).is_ok());
+
+        for start in [
+            &b"\xFF\xFB\x90\x64"[..],
+            b"OggS\x00\x02",
+            b"fLaC\x00\x00\x00\x22",
+            b"RIFF\x24\x00\x00\x00WAVEfmt ",
+            b"\x1A\x45\xDF\xA3\x9F\x42",
+        ] {
+            fs::write(&path, start).unwrap();
+            assert!(check(&path, Some("audio/mpeg"), None).is_ok());
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}